@@ -0,0 +1,23 @@
+//! Sends warnings and errors to Riemann using the `log` crate
+
+use log::{error, warn};
+use riemann_client::client::{BatchClient, BatchOptions};
+use riemann_client::logger::Logger;
+use riemann_client::Client;
+
+fn main() {
+    let client = BatchClient::new(
+        || Client::connect(&("localhost", 5555)),
+        BatchOptions::default(),
+    );
+
+    let mut logger = Logger::new(client);
+    logger.set_rate_limit(Some(100));
+    logger.init().unwrap();
+
+    warn!("disk usage above 80%");
+    error!("failed to write checkpoint");
+
+    // The global logger is never dropped, so flush it to send the batch.
+    log::logger().flush();
+}
@@ -17,7 +17,7 @@ fn main() {
             event.get_host(),
             event.get_time(),
             event.get_service(),
-            event.get_metric_f(),
+            event.metric().map_or(String::new(), |m| m.to_string()),
             event.get_state()
         );
     }
@@ -0,0 +1,19 @@
+//! Prints events matching a query as they arrive at the index
+
+use riemann_client::subscribe::{Subscription, WEBSOCKET_PORT};
+
+fn main() {
+    for event in Subscription::websocket("localhost", WEBSOCKET_PORT, "true") {
+        match event {
+            Ok(event) => println!(
+                "{:<10} {:<10} {:<55} {:<10} {:<10}",
+                event.get_host(),
+                event.get_time(),
+                event.get_service(),
+                event.metric().map_or(String::new(), |m| m.to_string()),
+                event.get_state()
+            ),
+            Err(err) => eprintln!("{:?}", err),
+        }
+    }
+}
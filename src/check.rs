@@ -0,0 +1,451 @@
+//! Run Nagios-style check plugins and convert their results into events.
+//!
+//! A plugin's exit code gives the state of the check: 0 is `ok`, 1 is
+//! `warning`, 2 is `critical` and anything else is `unknown`. The text before
+//! the first `|` in its output becomes the description. Performance data,
+//! after the `|`, is sent as an extra event for each label, named like
+//! `service label`, with a state from the label's warning and critical
+//! ranges.
+
+use std::io::Read;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::proto::{Attribute, Event};
+use super::utils::{now_micros, Error};
+use super::Result;
+
+/// The state for a plugin's exit code.
+pub fn state(code: Option<i32>) -> &'static str {
+    match code {
+        Some(0) => "ok",
+        Some(1) => "warning",
+        Some(2) => "critical",
+        _ => "unknown",
+    }
+}
+
+/// A threshold range in the format `[@]start:end`, where `~` as the start
+/// means negative infinity and a missing start means zero.
+///
+/// A value alerts if it is outside the range, or inside it if it starts with `@`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Range {
+    pub start: f64,
+    pub end: f64,
+    pub inside: bool,
+}
+
+impl Range {
+    /// Returns true if a value should alert.
+    pub fn alerts(&self, value: f64) -> bool {
+        let within = self.start <= value && value <= self.end;
+        within == self.inside
+    }
+}
+
+impl FromStr for Range {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::Parse(format!("Invalid range {:?}", s));
+        let (inside, range) = match s.strip_prefix('@') {
+            Some(range) => (true, range),
+            None => (false, s),
+        };
+        let (start, end) = match range.split_once(':') {
+            Some((start, end)) => (start, end),
+            None => ("", range),
+        };
+
+        let start = match start {
+            "" => 0.0,
+            "~" => f64::NEG_INFINITY,
+            start => start.parse().map_err(|_| invalid())?,
+        };
+        let end = match end {
+            "" => f64::INFINITY,
+            end => end.parse().map_err(|_| invalid())?,
+        };
+        Ok(Range { start, end, inside })
+    }
+}
+
+/// A single performance data value, `'label'=value[unit];[warn];[crit];[min];[max]`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PerfData {
+    pub label: String,
+    pub value: f64,
+    pub unit: String,
+    pub warning: Option<Range>,
+    pub critical: Option<Range>,
+}
+
+impl PerfData {
+    /// The state of this value from its warning and critical ranges.
+    pub fn state(&self) -> &'static str {
+        if self.critical.is_some_and(|r| r.alerts(self.value)) {
+            "critical"
+        } else if self.warning.is_some_and(|r| r.alerts(self.value)) {
+            "warning"
+        } else {
+            "ok"
+        }
+    }
+}
+
+/// Split performance data into items, respecting quoted labels containing spaces.
+fn split_perfdata(perfdata: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut quoted = false;
+    for c in perfdata.chars() {
+        match c {
+            '\'' => {
+                quoted = !quoted;
+                item.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !item.is_empty() {
+                    items.push(std::mem::take(&mut item));
+                }
+            }
+            c => item.push(c),
+        }
+    }
+    if !item.is_empty() {
+        items.push(item);
+    }
+    items
+}
+
+fn parse_perfdata_item(item: &str) -> Option<PerfData> {
+    let (label, rest) = item.rsplit_once('=')?;
+    let label = label.trim_matches('\'').replace("''", "'");
+    let mut fields = rest.split(';');
+
+    let value = fields.next()?;
+    let number_end = value
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .unwrap_or(value.len());
+    let range = |field: Option<&str>| field.filter(|f| !f.is_empty()).and_then(|f| f.parse().ok());
+
+    Some(PerfData {
+        label,
+        value: value[..number_end].parse().ok()?,
+        unit: value[number_end..].to_string(),
+        warning: range(fields.next()),
+        critical: range(fields.next()),
+    })
+}
+
+/// Split plugin output into its text and performance data.
+///
+/// Performance data can follow a `|` on the first line and on any line of
+/// the long text that follows it. Invalid performance data is skipped.
+pub fn parse_output(output: &str) -> (String, Vec<PerfData>) {
+    let mut text = Vec::new();
+    let mut perfdata = Vec::new();
+
+    let mut lines = output.lines();
+    if let Some(first) = lines.next() {
+        let (first_text, first_perfdata) = first.split_once('|').unwrap_or((first, ""));
+        text.push(first_text.trim());
+        perfdata.push(first_perfdata);
+    }
+
+    let mut in_perfdata = false;
+    for line in lines {
+        if in_perfdata {
+            perfdata.push(line);
+        } else if let Some((line_text, line_perfdata)) = line.split_once('|') {
+            text.push(line_text.trim_end());
+            perfdata.push(line_perfdata);
+            in_perfdata = true;
+        } else {
+            text.push(line.trim_end());
+        }
+    }
+
+    let perfdata = perfdata
+        .iter()
+        .flat_map(|p| split_perfdata(p))
+        .filter_map(|item| parse_perfdata_item(&item))
+        .collect();
+    (text.join("\n").trim().to_string(), perfdata)
+}
+
+/// Kill a plugin along with any processes it started.
+fn kill(child: &mut Child) {
+    #[cfg(unix)]
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Runs a check plugin and converts its result into events.
+#[derive(Clone, Debug)]
+pub struct CheckRunner {
+    command: Vec<String>,
+    service: String,
+    ttl: f32,
+    timeout: Duration,
+}
+
+impl CheckRunner {
+    /// Create a runner for a command and its arguments, using the command's
+    /// file name as the service.
+    pub fn new(command: Vec<String>) -> Self {
+        let service = command
+            .first()
+            .map(|c| c.rsplit('/').next().unwrap_or(c).to_string())
+            .unwrap_or_default();
+        CheckRunner {
+            command,
+            service,
+            ttl: 60.0,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Set the service of the check's event, and the prefix for performance data events.
+    pub fn set_service(&mut self, service: String) {
+        self.service = service;
+    }
+
+    /// Set the TTL of the events, in seconds. Defaults to 60 seconds.
+    pub fn set_ttl(&mut self, ttl: f32) {
+        self.ttl = ttl;
+    }
+
+    /// Set how long the plugin may run before it is killed and the check is
+    /// `critical`. Defaults to 30 seconds.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Run the command, returning its exit status (or `None` if it timed out) and output.
+    fn execute(&self) -> Result<(Option<ExitStatus>, String)> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| Error::Parse("No check command given".to_string()))?;
+        let mut command = Command::new(program);
+        command
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+        // Run the plugin in its own process group, so that anything it starts
+        // can be killed with it.
+        #[cfg(unix)]
+        command.process_group(0);
+        let mut child = command.spawn()?;
+
+        // Read the output on another thread so a full pipe can't block the plugin.
+        let mut stdout = child.stdout.take().expect("stdout is piped");
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let (done, finished) = mpsc::channel();
+        let buffer = Arc::clone(&output);
+        thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            while let Ok(n) = stdout.read(&mut chunk) {
+                if n == 0 {
+                    break;
+                }
+                buffer.lock().unwrap().extend_from_slice(&chunk[..n]);
+            }
+            let _ = done.send(());
+        });
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break Some(status);
+            }
+            if Instant::now() >= deadline {
+                kill(&mut child);
+                break None;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+
+        // A process the plugin left running can hold stdout open, so only
+        // wait for the rest of the output until the deadline.
+        let _ = finished.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        let output = String::from_utf8_lossy(&output.lock().unwrap()).into_owned();
+        Ok((status, output))
+    }
+
+    fn event(&self, service: String, state: &str) -> Event {
+        let mut event = Event::new();
+        event.set_time(now_micros() / 1_000_000);
+        event.set_service(service);
+        event.set_state(state.to_string());
+        event.set_ttl(self.ttl);
+        event
+    }
+
+    /// Run the check, returning an event for its result followed by an event
+    /// for each performance data value.
+    pub fn run(&self) -> Vec<Event> {
+        let (status, output) = match self.execute() {
+            Ok(result) => result,
+            Err(e) => {
+                let mut event = self.event(self.service.clone(), "unknown");
+                event.set_description(format!("Failed to run check: {}", e));
+                return vec![event];
+            }
+        };
+
+        let (text, perfdata) = parse_output(&output);
+        let mut event = match status {
+            Some(status) => self.event(self.service.clone(), state(status.code())),
+            None => self.event(self.service.clone(), "critical"),
+        };
+        event.set_description(match status {
+            Some(_) => text,
+            None => format!("Check timed out after {:?}", self.timeout),
+        });
+
+        let mut events = vec![event];
+        for data in perfdata {
+            let mut event = self.event(format!("{} {}", self.service, data.label), data.state());
+            event.set_metric_d(data.value);
+            if !data.unit.is_empty() {
+                let mut unit = Attribute::new();
+                unit.set_key("unit".to_string());
+                unit.set_value(data.unit);
+                event.set_attributes(vec![unit].into());
+            }
+            events.push(event);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{parse_output, CheckRunner, PerfData, Range};
+
+    fn sh(script: &str) -> CheckRunner {
+        let mut runner = CheckRunner::new(vec![
+            "/bin/sh".to_string(),
+            "-c".to_string(),
+            script.to_string(),
+        ]);
+        runner.set_service("disk".to_string());
+        runner
+    }
+
+    #[test]
+    fn ranges() {
+        let range: Range = "10".parse().unwrap();
+        assert!(range.alerts(-1.0));
+        assert!(!range.alerts(5.0));
+        assert!(range.alerts(11.0));
+
+        let range: Range = "~:10".parse().unwrap();
+        assert!(!range.alerts(-100.0));
+        assert!(range.alerts(11.0));
+
+        let range: Range = "10:".parse().unwrap();
+        assert!(range.alerts(9.0));
+        assert!(!range.alerts(1e9));
+
+        let range: Range = "@10:20".parse().unwrap();
+        assert!(range.alerts(15.0));
+        assert!(!range.alerts(25.0));
+
+        assert!("x:1".parse::<Range>().is_err());
+    }
+
+    #[test]
+    fn output() {
+        let (text, perfdata) = parse_output(
+            "DISK OK - free space: / 3326 MB (56%); | /=2643MB;5948;5958;0;5968\n\
+             / 15272 MB (77%);\n\
+             /boot 68 MB (69%); | /boot=68MB;88;93;0;98\n\
+             'home dir'=69%;@0:10;@0:5",
+        );
+        assert_eq!(
+            text,
+            "DISK OK - free space: / 3326 MB (56%);\n/ 15272 MB (77%);\n/boot 68 MB (69%);"
+        );
+        assert_eq!(
+            perfdata,
+            vec![
+                PerfData {
+                    label: "/".to_string(),
+                    value: 2643.0,
+                    unit: "MB".to_string(),
+                    warning: Some("5948".parse().unwrap()),
+                    critical: Some("5958".parse().unwrap()),
+                },
+                PerfData {
+                    label: "/boot".to_string(),
+                    value: 68.0,
+                    unit: "MB".to_string(),
+                    warning: Some("88".parse().unwrap()),
+                    critical: Some("93".parse().unwrap()),
+                },
+                PerfData {
+                    label: "home dir".to_string(),
+                    value: 69.0,
+                    unit: "%".to_string(),
+                    warning: Some("@0:10".parse().unwrap()),
+                    critical: Some("@0:5".parse().unwrap()),
+                },
+            ]
+        );
+        assert_eq!(perfdata[0].state(), "ok");
+
+        let (text, perfdata) = parse_output("PING OK");
+        assert_eq!(text, "PING OK");
+        assert!(perfdata.is_empty());
+    }
+
+    #[test]
+    fn run() {
+        let events = sh("echo 'DISK WARNING | used=95%;90;98'; exit 1").run();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].get_service(), "disk");
+        assert_eq!(events[0].get_state(), "warning");
+        assert_eq!(events[0].get_description(), "DISK WARNING");
+        assert_eq!(events[0].get_ttl(), 60.0);
+        assert_eq!(events[1].get_service(), "disk used");
+        assert_eq!(events[1].get_state(), "warning");
+        assert_eq!(events[1].get_metric_d(), 95.0);
+        assert_eq!(events[1].get_attributes()[0].get_value(), "%");
+
+        assert_eq!(sh("exit 2").run()[0].get_state(), "critical");
+        assert_eq!(sh("exit 7").run()[0].get_state(), "unknown");
+
+        let start = Instant::now();
+        let mut runner = sh("sleep 5; echo done");
+        runner.set_timeout(Duration::from_millis(50));
+        assert_eq!(runner.run()[0].get_state(), "critical");
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let start = Instant::now();
+        let mut runner = sh("sleep 5 & echo 'DISK OK'");
+        runner.set_timeout(Duration::from_millis(200));
+        let events = runner.run();
+        assert_eq!(events[0].get_state(), "ok");
+        assert_eq!(events[0].get_description(), "DISK OK");
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let events = CheckRunner::new(vec!["/nonexistent/check_foo".to_string()]).run();
+        assert_eq!(events[0].get_service(), "check_foo");
+        assert_eq!(events[0].get_state(), "unknown");
+    }
+}
@@ -0,0 +1,247 @@
+//! A client that queues events and sends them in batches from a background thread.
+
+use std::mem;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{
+    channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError,
+};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::super::proto::Event;
+use super::super::Result;
+use super::Client;
+
+/// Options controlling how a `BatchClient` queues and sends events.
+#[derive(Clone, Debug)]
+pub struct BatchOptions {
+    /// The maximum number of events sent in one message.
+    pub batch_size: usize,
+    /// The maximum time an event is queued before its batch is sent.
+    pub interval: Duration,
+    /// The maximum number of queued events. Events sent while the queue is full are dropped.
+    pub capacity: usize,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            batch_size: 100,
+            interval: Duration::from_secs(1),
+            capacity: 10_000,
+        }
+    }
+}
+
+/// How long `BatchClient::flush` waits for queued events to be sent.
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Command {
+    Event(Box<Event>),
+    /// Send the queued events, then reply.
+    Flush(Sender<()>),
+}
+
+/// A non-blocking client that sends events from a background thread.
+///
+/// The thread connects using the function passed to `BatchClient::new`, and
+/// calls it again to reconnect after a failed send. Events in a batch that
+/// can't be sent are dropped rather than retried. Dropping the client sends
+/// any queued events and waits for the thread to finish.
+pub struct BatchClient {
+    sender: Option<SyncSender<Command>>,
+    worker: Option<JoinHandle<()>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+struct Worker<F> {
+    connect: F,
+    client: Option<Client>,
+    buffer: Vec<Event>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl<F: FnMut() -> Result<Client>> Worker<F> {
+    fn run(mut self, receiver: Receiver<Command>, options: BatchOptions) {
+        let mut deadline = Instant::now() + options.interval;
+
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            let (flush, reply) = match receiver.recv_timeout(timeout) {
+                Ok(Command::Event(event)) => {
+                    self.buffer.push(*event);
+                    (self.buffer.len() >= options.batch_size, None)
+                }
+                Ok(Command::Flush(reply)) => (true, Some(reply)),
+                Err(RecvTimeoutError::Timeout) => (true, None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.flush();
+                    return;
+                }
+            };
+
+            if flush {
+                self.flush();
+                deadline = Instant::now() + options.interval;
+            }
+            if let Some(reply) = reply {
+                let _ = reply.send(());
+            }
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let events = mem::take(&mut self.buffer);
+        let count = events.len();
+
+        if self.client.is_none() {
+            self.client = (self.connect)().ok();
+        }
+
+        let sent = match self.client {
+            Some(ref mut client) => client.events(events).is_ok(),
+            None => false,
+        };
+
+        if !sent {
+            self.client = None;
+            self.dropped.fetch_add(count, Ordering::Relaxed);
+        }
+    }
+}
+
+impl BatchClient {
+    /// Start a background thread that sends events to the client returned by `connect`.
+    pub fn new<F>(connect: F, options: BatchOptions) -> Self
+    where
+        F: FnMut() -> Result<Client> + Send + 'static,
+    {
+        let (sender, receiver) = sync_channel(options.capacity);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let worker = Worker {
+            connect,
+            client: None,
+            buffer: Vec::with_capacity(options.batch_size),
+            dropped: dropped.clone(),
+        };
+
+        BatchClient {
+            sender: Some(sender),
+            worker: Some(thread::spawn(move || worker.run(receiver, options))),
+            dropped,
+        }
+    }
+
+    /// Queue an event to be sent, returning `false` if the queue was full and
+    /// the event was dropped.
+    pub fn send(&self, event: Event) -> bool {
+        let sender = self.sender.as_ref().expect("BatchClient used after drop");
+        match sender.try_send(Command::Event(Box::new(event))) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Send queued events without waiting for the interval, blocking until
+    /// they have been sent or dropped. Returns `false` if that took longer
+    /// than `FLUSH_TIMEOUT`.
+    pub fn flush(&self) -> bool {
+        let sender = match self.sender {
+            Some(ref sender) => sender,
+            None => return false,
+        };
+        let (reply, replied) = channel();
+        if sender.send(Command::Flush(reply)).is_err() {
+            return false;
+        }
+        replied.recv_timeout(FLUSH_TIMEOUT).is_ok()
+    }
+
+    /// The number of events dropped because the queue was full or they could not be sent.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for BatchClient {
+    fn drop(&mut self) {
+        // Closing the channel tells the worker to send its last batch and exit.
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl ::std::fmt::Debug for BatchClient {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "BatchClient {{ dropped: {} }}", self.dropped())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::super::super::proto::Event;
+    use super::super::super::Error;
+    use super::super::test_server::TestServer;
+    use super::{BatchClient, BatchOptions};
+
+    fn event(service: &str) -> Event {
+        let mut event = Event::new();
+        event.set_service(service.to_string());
+        event
+    }
+
+    fn services(events: Vec<Event>) -> Vec<String> {
+        events.iter().map(|e| e.get_service().to_string()).collect()
+    }
+
+    #[test]
+    fn send_batches() {
+        let server = TestServer::start();
+        let client = BatchClient::new(
+            server.connector(),
+            BatchOptions {
+                batch_size: 2,
+                interval: Duration::from_secs(60),
+                ..BatchOptions::default()
+            },
+        );
+
+        assert!(client.send(event("a")));
+        assert!(client.send(event("b")));
+        assert!(client.send(event("c")));
+        assert_eq!(services(server.events()), vec!["a", "b"]);
+
+        assert!(client.flush());
+        let events = server.events();
+        assert_eq!(services(events.clone()), vec!["c"]);
+        assert!(events[0].has_host());
+
+        client.send(event("d"));
+        drop(client);
+        assert_eq!(services(server.events()), vec!["d"]);
+    }
+
+    #[test]
+    fn drop_when_disconnected() {
+        let client = BatchClient::new(
+            || Err(Error::Riemann("unavailable".to_string())),
+            BatchOptions::default(),
+        );
+        client.send(event("a"));
+        client.send(event("b"));
+        assert!(client.flush());
+        assert_eq!(client.dropped(), 2);
+    }
+}
@@ -0,0 +1,147 @@
+//! A background thread sending a heartbeat event on an interval.
+
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use super::super::proto::Event;
+use super::super::utils::now_micros;
+use super::super::Result;
+use super::Client;
+
+/// Options controlling the heartbeat event and how often it is sent.
+#[derive(Clone, Debug)]
+pub struct HeartbeatOptions {
+    /// The service of the heartbeat event.
+    pub service: String,
+    /// The time between heartbeats.
+    pub interval: Duration,
+    /// How long Riemann keeps each heartbeat before it expires. Defaults to
+    /// twice the interval, so one missed heartbeat doesn't cause an expiry.
+    pub ttl: Option<Duration>,
+}
+
+impl Default for HeartbeatOptions {
+    fn default() -> Self {
+        HeartbeatOptions {
+            service: "heartbeat".to_string(),
+            interval: Duration::from_secs(10),
+            ttl: None,
+        }
+    }
+}
+
+/// Sends a `heartbeat` event with a TTL every interval, so that Riemann's
+/// expiry of the event signals that the process has stopped.
+///
+/// The thread connects using the function passed to `Heartbeat::start`, and
+/// calls it again to reconnect after a failed send. Dropping the heartbeat
+/// stops the thread without sending another event. It doesn't wait for the
+/// thread, which may be blocked connecting to an unreachable server.
+pub struct Heartbeat {
+    stop: Option<Sender<()>>,
+}
+
+impl HeartbeatOptions {
+    fn event(&self) -> Event {
+        let ttl = self.ttl.unwrap_or(self.interval * 2);
+        let mut event = Event::new();
+        event.set_time(now_micros() / 1_000_000);
+        event.set_service(self.service.clone());
+        event.set_state("ok".to_string());
+        event.set_ttl(ttl.as_secs_f32());
+        event
+    }
+}
+
+impl Heartbeat {
+    /// Start a thread sending heartbeats to the client returned by `connect`.
+    pub fn start<F>(mut connect: F, options: HeartbeatOptions) -> Self
+    where
+        F: FnMut() -> Result<Client> + Send + 'static,
+    {
+        let (stop, stopped) = channel();
+
+        thread::spawn(move || {
+            let mut client: Option<Client> = None;
+            loop {
+                if client.is_none() {
+                    client = connect().ok();
+                }
+                if let Some(ref mut c) = client {
+                    if c.event(options.event()).is_err() {
+                        client = None;
+                    }
+                }
+
+                match stopped.recv_timeout(options.interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+
+        Heartbeat { stop: Some(stop) }
+    }
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        // Closing the channel stops the thread once it is done sending.
+        self.stop.take();
+    }
+}
+
+impl ::std::fmt::Debug for Heartbeat {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "Heartbeat")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::super::super::Error;
+    use super::super::test_server::TestServer;
+    use super::{Heartbeat, HeartbeatOptions};
+
+    #[test]
+    fn send_heartbeats() {
+        let server = TestServer::start();
+        let heartbeat = Heartbeat::start(
+            server.connector(),
+            HeartbeatOptions {
+                interval: Duration::from_millis(20),
+                ..HeartbeatOptions::default()
+            },
+        );
+
+        for _ in 0..2 {
+            let events = server.events();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].get_service(), "heartbeat");
+            assert_eq!(events[0].get_state(), "ok");
+            assert_eq!(events[0].get_ttl(), 0.04);
+            assert!(events[0].has_host());
+        }
+
+        drop(heartbeat);
+    }
+
+    #[test]
+    fn drop_while_connecting() {
+        let heartbeat = Heartbeat::start(
+            || {
+                thread::sleep(Duration::from_secs(5));
+                Err(Error::Riemann("unavailable".to_string()))
+            },
+            HeartbeatOptions::default(),
+        );
+
+        let start = Instant::now();
+        drop(heartbeat);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
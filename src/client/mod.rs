@@ -4,10 +4,19 @@ use std::net::ToSocketAddrs;
 use std::time::Duration;
 
 use super::proto::{Event, Query};
-use super::transport::TCPTransport;
+use super::transport::{EventStream, TCPTransport};
 use super::Result;
 
-mod hostname;
+mod batch;
+mod heartbeat;
+pub(crate) mod hostname;
+mod order;
+#[cfg(test)]
+pub(crate) mod test_server;
+
+pub use self::batch::{BatchClient, BatchOptions, FLUSH_TIMEOUT};
+pub use self::heartbeat::{Heartbeat, HeartbeatOptions};
+pub use self::order::{Comparator, Order};
 
 /// Adds a `set_defaults()` method to `Event`
 trait SetDefaults {
@@ -77,16 +86,30 @@ impl Client {
         self.events(vec![event])
     }
 
-    /// Send a query and return a sorted list of events matching the query.
+    /// Send a query and return a list of events matching the query, sorted by service.
     pub fn query<T: Into<Query>>(&mut self, query: T) -> Result<Vec<Event>> {
-        let response = self.transport.send_query(query.into())?;
+        self.query_ordered(query, &Order::default())
+    }
+
+    /// Send a query and return a list of events matching the query, sorted by `order`.
+    pub fn query_ordered<T: Into<Query>>(&mut self, query: T, order: &Order) -> Result<Vec<Event>> {
+        let mut response = self.transport.send_query(query.into())?;
 
         Ok({
-            let mut events = Vec::from(response.get_events());
-            events.sort_by(|a, b| a.get_service().cmp(b.get_service()));
+            let mut events = response.take_events().into_vec();
+            order.sort(&mut events);
             events
         })
     }
+
+    /// Send a query and return an iterator over the matching events, in the
+    /// order the server sent them.
+    ///
+    /// Events are decoded one at a time as the response is read, instead of
+    /// decoding and copying the whole response before returning.
+    pub fn query_iter<T: Into<Query>>(&mut self, query: T) -> Result<EventStream<'_>> {
+        self.transport.send_query_stream(query.into())
+    }
 }
 
 #[cfg(test)]
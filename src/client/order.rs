@@ -0,0 +1,198 @@
+//! Orderings for the results of a query
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use super::super::proto::{Event, Metric};
+use super::super::utils::Error;
+use super::super::Result;
+
+/// A function comparing two events, for use with `Order::Custom`.
+pub type Comparator = Box<dyn Fn(&Event, &Event) -> Ordering>;
+
+/// How to sort the events returned by a query.
+#[derive(Default)]
+pub enum Order {
+    /// Leave events in the order the server returned them.
+    None,
+    /// Sort by service (the default).
+    #[default]
+    Service,
+    /// Sort by host, then by service.
+    HostService,
+    /// Sort by time, oldest first.
+    Time,
+    /// Sort by metric, smallest first, with events that have no metric last.
+    Metric,
+    /// Sort using a custom comparator.
+    Custom(Comparator),
+}
+
+fn time(event: &Event) -> i64 {
+    if event.has_time_micros() {
+        event.get_time_micros()
+    } else {
+        event.get_time() * 1_000_000
+    }
+}
+
+fn metric(event: &Event) -> Option<f64> {
+    event.metric().map(Metric::as_f64)
+}
+
+impl Order {
+    /// Compare two events using this ordering.
+    pub fn compare(&self, a: &Event, b: &Event) -> Ordering {
+        match *self {
+            Order::None => Ordering::Equal,
+            Order::Service => a.get_service().cmp(b.get_service()),
+            Order::HostService => a
+                .get_host()
+                .cmp(b.get_host())
+                .then_with(|| a.get_service().cmp(b.get_service())),
+            Order::Time => time(a).cmp(&time(b)),
+            Order::Metric => match (metric(a), metric(b)) {
+                (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+            Order::Custom(ref compare) => compare(a, b),
+        }
+    }
+
+    /// Sort a list of events in place using this ordering.
+    pub fn sort(&self, events: &mut [Event]) {
+        if let Order::None = *self {
+            return;
+        }
+        events.sort_by(|a, b| self.compare(a, b));
+    }
+}
+
+impl FromStr for Order {
+    type Err = Error;
+
+    /// Parse `none`, `service`, `host` (host then service), `time` or `metric`.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Order::None),
+            "service" => Ok(Order::Service),
+            "host" => Ok(Order::HostService),
+            "time" => Ok(Order::Time),
+            "metric" => Ok(Order::Metric),
+            _ => Err(Error::Parse(format!(
+                "Invalid order {:?}, expected none, service, host, time or metric",
+                s
+            ))),
+        }
+    }
+}
+
+impl ::std::fmt::Debug for Order {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let name = match *self {
+            Order::None => "None",
+            Order::Service => "Service",
+            Order::HostService => "HostService",
+            Order::Time => "Time",
+            Order::Metric => "Metric",
+            Order::Custom(_) => "Custom(..)",
+        };
+        write!(f, "Order::{}", name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::super::proto::Event;
+    use super::Order;
+
+    fn event(host: &str, service: &str, time: i64) -> Event {
+        let mut event = Event::new();
+        event.set_host(host.to_string());
+        event.set_service(service.to_string());
+        event.set_time(time);
+        event
+    }
+
+    fn order(order: Order, mut events: Vec<Event>) -> Vec<(String, String)> {
+        order.sort(&mut events);
+        events
+            .iter()
+            .map(|e| (e.get_host().to_string(), e.get_service().to_string()))
+            .collect()
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|&(h, s)| (h.to_string(), s.to_string()))
+            .collect()
+    }
+
+    fn events() -> Vec<Event> {
+        vec![event("b", "y", 3), event("a", "z", 1), event("a", "x", 2)]
+    }
+
+    #[test]
+    fn order_none() {
+        assert_eq!(
+            order(Order::None, events()),
+            pairs(&[("b", "y"), ("a", "z"), ("a", "x")])
+        );
+    }
+
+    #[test]
+    fn order_service() {
+        assert_eq!(
+            order(Order::default(), events()),
+            pairs(&[("a", "x"), ("b", "y"), ("a", "z")])
+        );
+    }
+
+    #[test]
+    fn order_host_service() {
+        assert_eq!(
+            order(Order::HostService, events()),
+            pairs(&[("a", "x"), ("a", "z"), ("b", "y")])
+        );
+    }
+
+    #[test]
+    fn order_time() {
+        assert_eq!(
+            order(Order::Time, events()),
+            pairs(&[("a", "z"), ("a", "x"), ("b", "y")])
+        );
+    }
+
+    #[test]
+    fn order_metric() {
+        let mut events = events();
+        events[0].set_metric_d(0.5);
+        events[2].set_metric_sint64(-1);
+        assert_eq!(
+            order(Order::Metric, events),
+            pairs(&[("a", "x"), ("b", "y"), ("a", "z")])
+        );
+    }
+
+    #[test]
+    fn order_from_str() {
+        assert_eq!(
+            order("host".parse().unwrap(), events()),
+            pairs(&[("a", "x"), ("a", "z"), ("b", "y")])
+        );
+        assert!("size".parse::<Order>().is_err());
+    }
+
+    #[test]
+    fn order_custom() {
+        let reverse = Order::Custom(Box::new(|a, b| b.get_service().cmp(a.get_service())));
+        assert_eq!(
+            order(reverse, events()),
+            pairs(&[("a", "z"), ("b", "y"), ("a", "x")])
+        );
+    }
+}
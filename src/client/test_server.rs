@@ -0,0 +1,85 @@
+//! A fake Riemann server for tests, which records the messages it receives.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::Duration;
+
+use ::protobuf::Message;
+
+use super::super::proto::{Event, Msg};
+use super::super::Result;
+use super::Client;
+
+pub struct TestServer {
+    pub port: u16,
+    messages: Receiver<Msg>,
+}
+
+fn handle(mut stream: TcpStream, messages: Sender<Msg>) {
+    loop {
+        let mut size = [0u8; 4];
+        if stream.read_exact(&mut size).is_err() {
+            return;
+        }
+        let mut bytes = vec![0u8; u32::from_be_bytes(size) as usize];
+        if stream.read_exact(&mut bytes).is_err() {
+            return;
+        }
+        let msg: Msg = Message::parse_from_bytes(&bytes).unwrap();
+
+        // Respond to a query for `error` as Riemann does to an invalid query.
+        let mut response = Msg::new();
+        if msg.get_query().get_string() == "error" {
+            response.set_ok(false);
+            response.set_error("parse error".to_string());
+        } else {
+            response.set_ok(true);
+        }
+        let bytes = response.write_to_bytes().unwrap();
+        stream
+            .write_all(&(bytes.len() as u32).to_be_bytes())
+            .unwrap();
+        stream.write_all(&bytes).unwrap();
+
+        if messages.send(msg).is_err() {
+            return;
+        }
+    }
+}
+
+impl TestServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (sender, messages) = channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let sender = sender.clone();
+                thread::spawn(move || handle(stream.unwrap(), sender));
+            }
+        });
+
+        TestServer { port, messages }
+    }
+
+    /// A function connecting a new client to the server.
+    pub fn connector(&self) -> impl FnMut() -> Result<Client> + Send + 'static {
+        let port = self.port;
+        move || Client::connect(&("127.0.0.1", port))
+    }
+
+    /// Wait for the next message sent to the server.
+    pub fn message(&self) -> Msg {
+        self.messages
+            .recv_timeout(Duration::from_secs(5))
+            .expect("No message received by test server")
+    }
+
+    /// Wait for the events of the next message sent to the server.
+    pub fn events(&self) -> Vec<Event> {
+        self.message().take_events().into_vec()
+    }
+}
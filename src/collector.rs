@@ -0,0 +1,425 @@
+//! Host metrics for Linux, read from `/proc` and `statvfs`.
+//!
+//! A `Collector` produces events for CPU, memory, load, disk and network
+//! usage, similar to `riemann-health` from riemann-tools. CPU and network
+//! usage are measured between calls to `collect`, so they are only included
+//! from the second call onwards.
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs;
+use std::io::Error as IoError;
+use std::mem;
+use std::time::Instant;
+
+use super::proto::Event;
+use super::utils::now_micros;
+use super::Result;
+
+/// The usage at which a metric's state becomes `warning` or `critical`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Threshold {
+    pub warning: f64,
+    pub critical: f64,
+}
+
+impl Threshold {
+    pub fn new(warning: f64, critical: f64) -> Self {
+        Threshold { warning, critical }
+    }
+
+    /// The state of a metric with this threshold.
+    pub fn state(&self, value: f64) -> &'static str {
+        if value >= self.critical {
+            "critical"
+        } else if value >= self.warning {
+            "warning"
+        } else {
+            "ok"
+        }
+    }
+}
+
+/// Thresholds for each of the collected metrics.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    /// The fraction of CPU time spent busy.
+    pub cpu: Threshold,
+    /// The fraction of memory in use.
+    pub memory: Threshold,
+    /// The one minute load average divided by the number of CPUs.
+    pub load: Threshold,
+    /// The fraction of each filesystem in use.
+    pub disk: Threshold,
+}
+
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            cpu: Threshold::new(0.9, 0.95),
+            memory: Threshold::new(0.85, 0.95),
+            load: Threshold::new(3.0, 8.0),
+            disk: Threshold::new(0.9, 0.95),
+        }
+    }
+}
+
+/// CPU time counters from `/proc/stat`, in clock ticks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct CpuTimes {
+    idle: u64,
+    total: u64,
+}
+
+/// Parse the total CPU times and the number of CPUs from `/proc/stat`.
+fn parse_stat(stat: &str) -> Option<(CpuTimes, usize)> {
+    let mut lines = stat.lines();
+    let fields: Vec<u64> = lines
+        .next()?
+        .strip_prefix("cpu ")?
+        .split_whitespace()
+        .filter_map(|f| f.parse().ok())
+        .collect();
+    if fields.len() < 4 {
+        return None;
+    }
+
+    // Idle time includes time spent waiting for IO, the fifth field.
+    let idle = fields[3] + fields.get(4).unwrap_or(&0);
+    let total = fields.iter().sum();
+    let cpus = lines
+        .filter(|l| l.starts_with("cpu") && l.as_bytes().get(3).is_some_and(u8::is_ascii_digit))
+        .count();
+    Some((CpuTimes { idle, total }, cpus.max(1)))
+}
+
+/// Parse the fraction of memory in use from `/proc/meminfo`.
+fn parse_meminfo(meminfo: &str) -> Option<f64> {
+    let values: HashMap<&str, f64> = meminfo
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let value = value.split_whitespace().next()?.parse().ok()?;
+            Some((key, value))
+        })
+        .collect();
+
+    let total = *values.get("MemTotal")?;
+    let available = match values.get("MemAvailable") {
+        Some(&available) => available,
+        None => values.get("MemFree")? + values.get("Buffers")? + values.get("Cached")?,
+    };
+    if total > 0.0 {
+        Some(1.0 - available / total)
+    } else {
+        None
+    }
+}
+
+/// Parse the one, five and fifteen minute load averages from `/proc/loadavg`.
+fn parse_loadavg(loadavg: &str) -> Option<[f64; 3]> {
+    let mut fields = loadavg.split_whitespace().map(|f| f.parse().ok());
+    Some([fields.next()??, fields.next()??, fields.next()??])
+}
+
+/// The bytes received and transmitted by each network interface.
+type NetCounters = HashMap<String, (u64, u64)>;
+
+/// Parse the bytes received and transmitted by each interface from `/proc/net/dev`.
+fn parse_net_dev(net_dev: &str) -> NetCounters {
+    net_dev
+        .lines()
+        .filter_map(|line| {
+            let (name, counters) = line.split_once(':')?;
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .filter_map(|f| f.parse().ok())
+                .collect();
+            Some((
+                name.trim().to_string(),
+                (*counters.first()?, *counters.get(8)?),
+            ))
+        })
+        .collect()
+}
+
+/// Parse the mount points of block devices from `/proc/mounts`.
+fn parse_mounts(mounts: &str) -> Vec<String> {
+    let mut points: Vec<String> = Vec::new();
+    for line in mounts.lines() {
+        let mut fields = line.split_whitespace();
+        if let (Some(device), Some(point)) = (fields.next(), fields.next()) {
+            // Spaces and other characters in mount points are escaped as octal.
+            let point = point.replace("\\040", " ").replace("\\011", "\t");
+            if device.starts_with("/dev/") && !points.contains(&point) {
+                points.push(point);
+            }
+        }
+    }
+    points
+}
+
+/// The fraction of a filesystem in use, as reported by `df`.
+fn disk_usage(path: &str) -> Result<f64> {
+    let path = CString::new(path).map_err(IoError::from)?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(IoError::last_os_error().into());
+    }
+
+    let used = stat.f_blocks.saturating_sub(stat.f_bfree) as f64;
+    let available = stat.f_bavail as f64;
+    Ok(if used + available > 0.0 {
+        used / (used + available)
+    } else {
+        0.0
+    })
+}
+
+fn event(service: &str, metric: f64, state: &str, description: String) -> Event {
+    let mut event = Event::new();
+    event.set_time(now_micros() / 1_000_000);
+    event.set_service(service.to_string());
+    event.set_metric_d(metric);
+    event.set_state(state.to_string());
+    event.set_description(description);
+    event
+}
+
+/// Collects host metrics as events, keeping the previous counters to measure rates.
+#[derive(Debug)]
+pub struct Collector {
+    thresholds: Thresholds,
+    cpu: Option<CpuTimes>,
+    network: Option<(Instant, NetCounters)>,
+}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Collector::new(Thresholds::default())
+    }
+}
+
+impl Collector {
+    pub fn new(thresholds: Thresholds) -> Self {
+        Collector {
+            thresholds,
+            cpu: None,
+            network: None,
+        }
+    }
+
+    /// Read the current metrics, returning an event for each.
+    pub fn collect(&mut self) -> Result<Vec<Event>> {
+        let mut events = Vec::new();
+
+        let stat = fs::read_to_string("/proc/stat")?;
+        let cpus = match parse_stat(&stat) {
+            Some((cpu, cpus)) => {
+                events.extend(self.cpu_event(cpu));
+                cpus
+            }
+            None => 1,
+        };
+        events.extend(self.memory_event(&fs::read_to_string("/proc/meminfo")?));
+        events.extend(self.load_event(&fs::read_to_string("/proc/loadavg")?, cpus));
+        events.extend(self.disk_events(&fs::read_to_string("/proc/mounts")?));
+        events.extend(self.network_events(&fs::read_to_string("/proc/net/dev")?));
+
+        Ok(events)
+    }
+
+    fn cpu_event(&mut self, cpu: CpuTimes) -> Option<Event> {
+        let previous = self.cpu.replace(cpu)?;
+        let total = cpu.total.checked_sub(previous.total)?;
+        let idle = cpu.idle.checked_sub(previous.idle)?;
+        if total == 0 {
+            return None;
+        }
+
+        let used = 1.0 - idle as f64 / total as f64;
+        Some(event(
+            "cpu",
+            used,
+            self.thresholds.cpu.state(used),
+            format!("{:.2}% CPU used", used * 100.0),
+        ))
+    }
+
+    fn memory_event(&self, meminfo: &str) -> Option<Event> {
+        let used = parse_meminfo(meminfo)?;
+        Some(event(
+            "memory",
+            used,
+            self.thresholds.memory.state(used),
+            format!("{:.2}% memory used", used * 100.0),
+        ))
+    }
+
+    fn load_event(&self, loadavg: &str, cpus: usize) -> Option<Event> {
+        let load = parse_loadavg(loadavg)?;
+        let per_cpu = load[0] / cpus as f64;
+        Some(event(
+            "load",
+            per_cpu,
+            self.thresholds.load.state(per_cpu),
+            format!(
+                "1-minute load average per CPU is {:.2} ({} {} {} across {} CPUs)",
+                per_cpu, load[0], load[1], load[2], cpus
+            ),
+        ))
+    }
+
+    fn disk_events(&self, mounts: &str) -> Vec<Event> {
+        parse_mounts(mounts)
+            .into_iter()
+            .filter_map(|point| {
+                let used = disk_usage(&point).ok()?;
+                Some(event(
+                    &format!("disk {}", point),
+                    used,
+                    self.thresholds.disk.state(used),
+                    format!("{:.2}% of {} used", used * 100.0, point),
+                ))
+            })
+            .collect()
+    }
+
+    fn network_events(&mut self, net_dev: &str) -> Vec<Event> {
+        let now = Instant::now();
+        let counters = parse_net_dev(net_dev);
+        let previous = self.network.replace((now, counters.clone()));
+
+        let (then, previous) = match previous {
+            Some(previous) => previous,
+            None => return Vec::new(),
+        };
+        let elapsed = now.duration_since(then).as_secs_f64();
+        if elapsed <= 0.0 {
+            return Vec::new();
+        }
+
+        let mut names: Vec<&String> = counters.keys().filter(|name| *name != "lo").collect();
+        names.sort();
+
+        let mut events = Vec::new();
+        for name in names {
+            let (rx, tx) = counters[name];
+            if let Some(&(previous_rx, previous_tx)) = previous.get(name) {
+                for (direction, bytes) in [
+                    ("rx", rx.saturating_sub(previous_rx)),
+                    ("tx", tx.saturating_sub(previous_tx)),
+                ] {
+                    let rate = bytes as f64 / elapsed;
+                    events.push(event(
+                        &format!("network {} {} bytes", name, direction),
+                        rate,
+                        "ok",
+                        format!("{:.0} bytes/s", rate),
+                    ));
+                }
+            }
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        parse_loadavg, parse_meminfo, parse_mounts, parse_net_dev, parse_stat, Collector, CpuTimes,
+        Threshold,
+    };
+
+    const STAT: &str = "cpu  100 0 50 800 50 0 0 0 0 0
+cpu0 50 0 25 400 25 0 0 0 0 0
+cpu1 50 0 25 400 25 0 0 0 0 0
+intr 12345
+";
+
+    const MEMINFO: &str = "MemTotal:       1000 kB
+MemFree:         100 kB
+MemAvailable:    250 kB
+Buffers:          50 kB
+Cached:          100 kB
+";
+
+    const NET_DEV: &str = "Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:     500       5    0    0    0     0          0         0      500       5    0    0    0     0       0          0
+  eth0:    1000      10    0    0    0     0          0         0     2000      20    0    0    0     0       0          0
+";
+
+    #[test]
+    fn threshold_state() {
+        let threshold = Threshold::new(0.5, 0.9);
+        assert_eq!(threshold.state(0.1), "ok");
+        assert_eq!(threshold.state(0.5), "warning");
+        assert_eq!(threshold.state(0.95), "critical");
+    }
+
+    #[test]
+    fn parse_proc_files() {
+        assert_eq!(
+            parse_stat(STAT),
+            Some((
+                CpuTimes {
+                    idle: 850,
+                    total: 1000
+                },
+                2
+            ))
+        );
+        assert_eq!(parse_meminfo(MEMINFO), Some(0.75));
+        assert_eq!(
+            parse_meminfo("MemTotal: 1000 kB\nMemFree: 100 kB\nBuffers: 50 kB\nCached: 100 kB"),
+            Some(0.75)
+        );
+        assert_eq!(
+            parse_loadavg("0.50 1.25 2.00 1/234 5678\n"),
+            Some([0.5, 1.25, 2.0])
+        );
+
+        let net_dev = parse_net_dev(NET_DEV);
+        assert_eq!(net_dev.len(), 2);
+        assert_eq!(net_dev["eth0"], (1000, 2000));
+
+        let mounts = "proc /proc proc rw 0 0\n/dev/sda1 / ext4 rw 0 0\n/dev/sdb1 /mnt/my\\040disk ext4 rw 0 0\n/dev/sda1 / ext4 rw 0 0\n";
+        assert_eq!(parse_mounts(mounts), vec!["/", "/mnt/my disk"]);
+    }
+
+    #[test]
+    fn cpu_usage_between_calls() {
+        let mut collector = Collector::default();
+        assert!(collector
+            .cpu_event(CpuTimes {
+                idle: 800,
+                total: 1000
+            })
+            .is_none());
+
+        let event = collector
+            .cpu_event(CpuTimes {
+                idle: 802,
+                total: 1100,
+            })
+            .unwrap();
+        assert_eq!(event.get_service(), "cpu");
+        assert_eq!(event.get_metric_d(), 0.98);
+        assert_eq!(event.get_state(), "critical");
+    }
+
+    #[test]
+    fn collect() {
+        let mut collector = Collector::default();
+        let services: Vec<String> = collector
+            .collect()
+            .unwrap()
+            .iter()
+            .map(|e| e.get_service().to_string())
+            .collect();
+        assert!(services.contains(&"memory".to_string()));
+        assert!(services.contains(&"load".to_string()));
+        assert!(!services.contains(&"cpu".to_string()));
+    }
+}
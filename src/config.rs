@@ -0,0 +1,329 @@
+//! Connection settings from a config file and environment variables.
+//!
+//! A config file is TOML, with settings for the default connection at the
+//! top level and named profiles that override them:
+//!
+//! ```toml
+//! host = "riemann.example.com"
+//!
+//! [profiles.prod]
+//! host = "riemann.prod.example.com"
+//! mtls = true
+//! cafile = "/etc/riemann/ca.pem"
+//! cert = "/etc/riemann/client.pem"
+//! key = "/etc/riemann/client.key"
+//! ```
+//!
+//! Relative `cafile`, `cert` and `key` paths in a config file are relative to
+//! the directory containing it.
+//!
+//! The environment variables `RIEMANN_HOST`, `RIEMANN_PORT`, `RIEMANN_MTLS`,
+//! `RIEMANN_CAFILE`, `RIEMANN_CERT` and `RIEMANN_KEY` set the same fields.
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use toml::{Table, Value};
+
+use super::client::Client;
+use super::utils::Error;
+use super::Result;
+
+/// Settings for connecting to a Riemann server, where unset fields use the
+/// value from another source or the default.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub mtls: Option<bool>,
+    pub cafile: Option<String>,
+    pub cert: Option<String>,
+    pub key: Option<String>,
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" | "" => Ok(false),
+        _ => Err(Error::Parse(format!(
+            "Invalid value for {}: {:?}",
+            name, value
+        ))),
+    }
+}
+
+impl Profile {
+    /// Read settings from the `RIEMANN_*` environment variables.
+    pub fn from_env() -> Result<Self> {
+        Profile::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars<F: Fn(&str) -> Option<String>>(var: F) -> Result<Self> {
+        let port = match var("RIEMANN_PORT") {
+            Some(port) => Some(port.parse().map_err(|_| {
+                Error::Parse(format!("Invalid value for RIEMANN_PORT: {:?}", port))
+            })?),
+            None => None,
+        };
+        let mtls = match var("RIEMANN_MTLS") {
+            Some(mtls) => Some(parse_bool("RIEMANN_MTLS", &mtls)?),
+            None => None,
+        };
+        Ok(Profile {
+            host: var("RIEMANN_HOST"),
+            port,
+            mtls,
+            cafile: var("RIEMANN_CAFILE"),
+            cert: var("RIEMANN_CERT"),
+            key: var("RIEMANN_KEY"),
+        })
+    }
+
+    fn from_table(table: &Table, name: &str) -> Result<Self> {
+        let invalid = |key: &str, expected: &str| {
+            Error::Parse(format!("Expected {} in {} to be {}", key, name, expected))
+        };
+        let string = |key: &str| match table.get(key) {
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(invalid(key, "a string")),
+            None => Ok(None),
+        };
+
+        let port = match table.get("port") {
+            Some(Value::Integer(port)) => {
+                Some(u16::try_from(*port).map_err(|_| invalid("port", "a port number"))?)
+            }
+            Some(_) => return Err(invalid("port", "a port number")),
+            None => None,
+        };
+        let mtls = match table.get("mtls") {
+            Some(Value::Boolean(mtls)) => Some(*mtls),
+            Some(_) => return Err(invalid("mtls", "true or false")),
+            None => None,
+        };
+        Ok(Profile {
+            host: string("host")?,
+            port,
+            mtls,
+            cafile: string("cafile")?,
+            cert: string("cert")?,
+            key: string("key")?,
+        })
+    }
+
+    /// Make relative file paths relative to `dir`.
+    fn relative_to(self, dir: &Path) -> Profile {
+        let resolve =
+            |path: Option<String>| path.map(|path| dir.join(path).to_string_lossy().into_owned());
+        Profile {
+            cafile: resolve(self.cafile),
+            cert: resolve(self.cert),
+            key: resolve(self.key),
+            ..self
+        }
+    }
+
+    /// Use the settings from `other` for any settings that are not set.
+    pub fn or(self, other: Profile) -> Profile {
+        Profile {
+            host: self.host.or(other.host),
+            port: self.port.or(other.port),
+            mtls: self.mtls.or(other.mtls),
+            cafile: self.cafile.or(other.cafile),
+            cert: self.cert.or(other.cert),
+            key: self.key.or(other.key),
+        }
+    }
+
+    /// The host to connect to, defaulting to `localhost`.
+    pub fn host(&self) -> &str {
+        self.host.as_deref().unwrap_or("localhost")
+    }
+
+    /// The port to connect to, defaulting to 5555.
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(5555)
+    }
+
+    /// Connect using these settings, with mTLS if `mtls` is true.
+    pub fn connect(&self) -> Result<Client> {
+        if !self.mtls.unwrap_or(false) {
+            return Client::connect(&(self.host(), self.port()));
+        }
+        match (&self.cafile, &self.cert, &self.key) {
+            (Some(cafile), Some(cert), Some(key)) => {
+                Client::connect_tls(self.host(), self.port(), cafile, cert, key)
+            }
+            _ => Err(Error::Parse(
+                "A CA certificate, certificate and key are required for mTLS".to_string(),
+            )),
+        }
+    }
+}
+
+/// The settings in a config file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    pub defaults: Profile,
+    pub profiles: HashMap<String, Profile>,
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let table: Table = s
+            .parse()
+            .map_err(|e: toml::de::Error| Error::Parse(e.message().to_string()))?;
+
+        let mut profiles = HashMap::new();
+        match table.get("profiles") {
+            Some(Value::Table(tables)) => {
+                for (name, profile) in tables {
+                    let profile = match profile {
+                        Value::Table(profile) => Profile::from_table(profile, name)?,
+                        _ => {
+                            return Err(Error::Parse(format!(
+                                "Expected profile {} to be a table",
+                                name
+                            )))
+                        }
+                    };
+                    profiles.insert(name.clone(), profile);
+                }
+            }
+            Some(_) => return Err(Error::Parse("Expected profiles to be a table".to_string())),
+            None => {}
+        }
+
+        Ok(Config {
+            defaults: Profile::from_table(&table, "the config file")?,
+            profiles,
+        })
+    }
+}
+
+impl Config {
+    /// The default config file, `riemann-cli.toml` in `$XDG_CONFIG_HOME` or `~/.config`.
+    pub fn default_path() -> Option<PathBuf> {
+        env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("riemann-cli.toml"))
+    }
+
+    /// Read a config file, making relative file paths relative to its directory.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let config: Config = fs::read_to_string(path)?.parse().map_err(|e| match e {
+            Error::Parse(message) => Error::Parse(format!(
+                "Invalid config file {}: {}",
+                path.display(),
+                message
+            )),
+            e => e,
+        })?;
+        Ok(config.relative_to(path.parent().unwrap_or_else(|| Path::new(""))))
+    }
+
+    fn relative_to(self, dir: &Path) -> Config {
+        Config {
+            defaults: self.defaults.relative_to(dir),
+            profiles: self
+                .profiles
+                .into_iter()
+                .map(|(name, profile)| (name, profile.relative_to(dir)))
+                .collect(),
+        }
+    }
+
+    /// The settings for a profile, using the top level settings for any that
+    /// the profile doesn't set, or just the top level settings if `name` is `None`.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        match name {
+            Some(name) => match self.profiles.get(name) {
+                Some(profile) => Ok(profile.clone().or(self.defaults.clone())),
+                None => Err(Error::Parse(format!("Unknown profile {:?}", name))),
+            },
+            None => Ok(self.defaults.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    use super::{Config, Profile};
+
+    const CONFIG: &str = r#"
+host = "riemann.example.com"
+port = 5555
+
+[profiles.prod]
+host = "riemann.prod.example.com"
+mtls = true
+cafile = "ca.pem"
+cert = "client.pem"
+key = "client.key"
+"#;
+
+    #[test]
+    fn profiles() {
+        let config: Config = CONFIG.parse().unwrap();
+        assert_eq!(config.profile(None).unwrap().host(), "riemann.example.com");
+
+        let prod = config.profile(Some("prod")).unwrap();
+        assert_eq!(prod.host(), "riemann.prod.example.com");
+        assert_eq!(prod.port(), 5555);
+        assert_eq!(prod.mtls, Some(true));
+        assert_eq!(prod.key.as_deref(), Some("client.key"));
+
+        assert!(config.profile(Some("staging")).is_err());
+        assert!("port = \"x\"".parse::<Config>().is_err());
+        assert!("port = 70000".parse::<Config>().is_err());
+        assert!("[profiles]\nprod = 1".parse::<Config>().is_err());
+        assert!("host = ".parse::<Config>().is_err());
+    }
+
+    #[test]
+    fn relative_paths() {
+        let config: Config = CONFIG.parse().unwrap();
+        let config = config.relative_to(Path::new("/etc/riemann"));
+        let prod = config.profile(Some("prod")).unwrap();
+        assert_eq!(prod.cafile.as_deref(), Some("/etc/riemann/ca.pem"));
+
+        let config: Config = "cert = \"/tmp/client.pem\"".parse().unwrap();
+        let config = config.relative_to(Path::new("/etc/riemann"));
+        assert_eq!(config.defaults.cert.as_deref(), Some("/tmp/client.pem"));
+    }
+
+    #[test]
+    fn precedence() {
+        let mut vars = HashMap::new();
+        vars.insert("RIEMANN_HOST", "env.example.com");
+        vars.insert("RIEMANN_MTLS", "0");
+        let env = Profile::from_vars(|name| vars.get(name).map(|v| v.to_string())).unwrap();
+
+        let flags = Profile {
+            port: Some(5556),
+            ..Profile::default()
+        };
+        let config: Config = CONFIG.parse().unwrap();
+        let profile = flags.or(env).or(config.profile(Some("prod")).unwrap());
+
+        assert_eq!(profile.host(), "env.example.com");
+        assert_eq!(profile.port(), 5556);
+        assert_eq!(profile.mtls, Some(false));
+        assert_eq!(profile.cafile.as_deref(), Some("ca.pem"));
+
+        assert_eq!(Profile::default().host(), "localhost");
+        assert!(Profile::from_vars(|_| Some("x".to_string())).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Run a command and report how it went as an event.
+//!
+//! The event's `metric_d` is the wall-clock duration of the command in
+//! seconds, its state is `ok` if the command succeeded and `critical`
+//! otherwise, and its description is the last lines the command wrote to
+//! stderr. This is meant for wrapping jobs such as cron scripts.
+
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::proto::{Attribute, Event};
+use super::utils::{now_micros, Error};
+use super::Result;
+
+/// The number of lines of stderr kept for the description.
+pub const TAIL_LINES: usize = 10;
+
+/// How long to wait for the rest of stderr after the command exits, in case
+/// a process it left running still has stderr open.
+const STDERR_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The result of running a command.
+#[derive(Debug)]
+pub struct Execution {
+    pub status: ExitStatus,
+    pub duration: Duration,
+    /// The last `TAIL_LINES` lines written to stderr.
+    pub stderr: String,
+}
+
+impl Execution {
+    /// The signal that killed the command, if it was killed by one.
+    pub fn signal(&self) -> Option<i32> {
+        #[cfg(unix)]
+        return self.status.signal();
+        #[cfg(not(unix))]
+        return None;
+    }
+
+    /// The command's exit code, or 128 plus the signal that killed it, as
+    /// reported by a shell.
+    pub fn exit_code(&self) -> i32 {
+        match (self.status.code(), self.signal()) {
+            (Some(code), _) => code,
+            (None, Some(signal)) => 128 + signal,
+            (None, None) => 1,
+        }
+    }
+
+    /// Build an event for this execution.
+    pub fn event(&self, service: &str) -> Event {
+        let mut event = Event::new();
+        event.set_time(now_micros() / 1_000_000);
+        event.set_service(service.to_string());
+        event.set_metric_d(self.duration.as_secs_f64());
+        let state = if self.status.success() {
+            "ok"
+        } else {
+            "critical"
+        };
+        event.set_state(state.to_string());
+        event.set_description(self.stderr.clone());
+
+        let mut attribute = Attribute::new();
+        match (self.status.code(), self.signal()) {
+            (Some(code), _) => {
+                attribute.set_key("exit_code".to_string());
+                attribute.set_value(code.to_string());
+            }
+            (None, Some(signal)) => {
+                attribute.set_key("signal".to_string());
+                attribute.set_value(signal.to_string());
+            }
+            (None, None) => return event,
+        }
+        event.set_attributes(vec![attribute].into());
+        event
+    }
+}
+
+/// Run a command and its arguments, waiting for it to exit.
+///
+/// The command's stdout is inherited, and its stderr is copied to ours as it
+/// is written.
+pub fn run(command: &[String]) -> Result<Execution> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| Error::Parse("No command given".to_string()))?;
+
+    let start = Instant::now();
+    let mut child = Command::new(program)
+        .args(args)
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let tail = Arc::new(Mutex::new(VecDeque::with_capacity(TAIL_LINES + 1)));
+    let (done, finished) = mpsc::channel();
+    let lines = Arc::clone(&tail);
+    thread::spawn(move || {
+        let mut reader = BufReader::new(stderr);
+        let mut line = Vec::new();
+        while let Ok(size) = reader.read_until(b'\n', &mut line) {
+            if size == 0 {
+                break;
+            }
+            let _ = io::stderr().write_all(&line);
+            let mut lines = lines.lock().unwrap();
+            lines.push_back(String::from_utf8_lossy(&line).trim_end().to_string());
+            if lines.len() > TAIL_LINES {
+                lines.pop_front();
+            }
+            line.clear();
+        }
+        let _ = done.send(());
+    });
+
+    let status = child.wait()?;
+    let duration = start.elapsed();
+    let _ = finished.recv_timeout(STDERR_TIMEOUT);
+    let stderr = Vec::from(tail.lock().unwrap().clone()).join("\n");
+    Ok(Execution {
+        status,
+        duration,
+        stderr,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::run;
+
+    fn sh(script: &str) -> Vec<String> {
+        vec!["/bin/sh".to_string(), "-c".to_string(), script.to_string()]
+    }
+
+    #[test]
+    fn success() {
+        let execution = run(&sh("sleep 0.1")).unwrap();
+        let event = execution.event("backup");
+        assert_eq!(event.get_service(), "backup");
+        assert_eq!(event.get_state(), "ok");
+        assert!(event.get_metric_d() >= 0.1);
+        assert_eq!(event.get_attributes()[0].get_value(), "0");
+    }
+
+    #[test]
+    fn failure() {
+        let execution = run(&sh("seq 1 15 >&2; exit 3")).unwrap();
+        assert_eq!(execution.status.code(), Some(3));
+        let event = execution.event("backup");
+        assert_eq!(event.get_state(), "critical");
+        assert_eq!(
+            event.get_description(),
+            "6\n7\n8\n9\n10\n11\n12\n13\n14\n15"
+        );
+        assert_eq!(event.get_attributes()[0].get_value(), "3");
+
+        assert!(run(&["/nonexistent/command".to_string()]).is_err());
+        assert!(run(&[]).is_err());
+    }
+
+    #[test]
+    fn signal() {
+        let execution = run(&sh("kill -9 $$")).unwrap();
+        assert_eq!(execution.signal(), Some(9));
+        assert_eq!(execution.exit_code(), 137);
+        let event = execution.event("backup");
+        assert_eq!(event.get_state(), "critical");
+        assert_eq!(event.get_attributes()[0].get_key(), "signal");
+        assert_eq!(event.get_attributes()[0].get_value(), "9");
+    }
+
+    #[test]
+    fn background() {
+        let start = Instant::now();
+        let execution = run(&sh("echo started >&2; sleep 5 &")).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(execution.stderr, "started");
+    }
+}
@@ -0,0 +1,401 @@
+//! Write events as a table, JSON or delimited text, for displaying query results.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use serde_json::{Map, Value};
+
+use super::json::format_time;
+use super::proto::{Event, Metric};
+use super::utils::Error;
+use super::Result;
+
+/// An output format for events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Columns aligned with spaces, with a header.
+    Table,
+    /// A JSON array of objects.
+    Json,
+    /// A JSON object on each line.
+    Jsonl,
+    /// Comma separated values, with a header.
+    Csv,
+    /// Tab separated values, with a header.
+    Tsv,
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(Format::Table),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            _ => Err(Error::Parse(format!(
+                "Invalid format {:?}, expected table, json, jsonl, csv or tsv",
+                s
+            ))),
+        }
+    }
+}
+
+/// A column of output, either an event field or the value of an attribute.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Column {
+    Host,
+    Service,
+    State,
+    Metric,
+    Time,
+    Ttl,
+    Description,
+    Tags,
+    /// All attributes, as `key=value` pairs.
+    Attributes,
+    Attribute(String),
+}
+
+/// The columns used when none are given: host, time, service, metric and state.
+pub const DEFAULT_COLUMNS: [Column; 5] = [
+    Column::Host,
+    Column::Time,
+    Column::Service,
+    Column::Metric,
+    Column::State,
+];
+
+impl FromStr for Column {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "host" => Column::Host,
+            "service" => Column::Service,
+            "state" => Column::State,
+            "metric" => Column::Metric,
+            "time" => Column::Time,
+            "ttl" => Column::Ttl,
+            "description" => Column::Description,
+            "tags" => Column::Tags,
+            "attributes" => Column::Attributes,
+            "" => return Err(Error::Parse("Empty column name".to_string())),
+            attribute => Column::Attribute(attribute.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for Column {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Column::Host => "host",
+            Column::Service => "service",
+            Column::State => "state",
+            Column::Metric => "metric",
+            Column::Time => "time",
+            Column::Ttl => "ttl",
+            Column::Description => "description",
+            Column::Tags => "tags",
+            Column::Attributes => "attributes",
+            Column::Attribute(key) => key,
+        })
+    }
+}
+
+/// Parse a comma separated list of columns.
+pub fn parse_columns(s: &str) -> Result<Vec<Column>> {
+    s.split(',').map(|column| column.trim().parse()).collect()
+}
+
+impl Column {
+    /// The value of this column for an event as JSON, or null if it is not set.
+    fn json(&self, event: &Event) -> Value {
+        let string = |has: bool, value: &str| match has {
+            true => Value::String(value.to_string()),
+            false => Value::Null,
+        };
+        match self {
+            Column::Host => string(event.has_host(), event.get_host()),
+            Column::Service => string(event.has_service(), event.get_service()),
+            Column::State => string(event.has_state(), event.get_state()),
+            Column::Description => string(event.has_description(), event.get_description()),
+            Column::Metric => match event.metric() {
+                Some(Metric::Int(metric)) => metric.into(),
+                Some(metric) => metric.as_f64().into(),
+                None => Value::Null,
+            },
+            Column::Time if event.has_time_micros() => format_time(event.get_time_micros()).into(),
+            Column::Time if event.has_time() => format_time(event.get_time() * 1_000_000).into(),
+            Column::Time => Value::Null,
+            Column::Ttl if event.has_ttl() => f64::from(event.get_ttl()).into(),
+            Column::Ttl => Value::Null,
+            Column::Tags => event.get_tags().into(),
+            Column::Attributes => Value::Object(
+                event
+                    .get_attributes()
+                    .iter()
+                    .map(|a| (a.get_key().to_string(), a.get_value().into()))
+                    .collect(),
+            ),
+            Column::Attribute(key) => event
+                .get_attributes()
+                .iter()
+                .find(|a| a.get_key() == key)
+                .map_or(Value::Null, |a| a.get_value().into()),
+        }
+    }
+
+    /// The value of this column for an event as text, or an empty string if it is not set.
+    fn text(&self, event: &Event) -> String {
+        match self {
+            Column::Metric => event.metric().map_or(String::new(), |m| m.to_string()),
+            Column::Time if event.has_time_micros() => {
+                let micros = event.get_time_micros();
+                let time = format!(
+                    "{}.{:06}",
+                    micros.div_euclid(1_000_000),
+                    micros.rem_euclid(1_000_000)
+                );
+                time.trim_end_matches('0').trim_end_matches('.').to_string()
+            }
+            Column::Time if event.has_time() => event.get_time().to_string(),
+            Column::Time => String::new(),
+            Column::Tags => event.get_tags().join(","),
+            Column::Attributes => event
+                .get_attributes()
+                .iter()
+                .map(|a| format!("{}={}", a.get_key(), a.get_value()))
+                .collect::<Vec<_>>()
+                .join(","),
+            column => match column.json(event) {
+                Value::Null => String::new(),
+                Value::String(string) => string,
+                value => value.to_string(),
+            },
+        }
+    }
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn tsv_field(field: &str) -> String {
+    field
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn write_rows<W: Write>(
+    out: &mut W,
+    events: &[Event],
+    columns: &[Column],
+    separator: &str,
+    escape: fn(&str) -> String,
+) -> io::Result<()> {
+    let header: Vec<String> = columns.iter().map(|c| escape(&c.to_string())).collect();
+    writeln!(out, "{}", header.join(separator))?;
+    for event in events {
+        let row: Vec<String> = columns.iter().map(|c| escape(&c.text(event))).collect();
+        writeln!(out, "{}", row.join(separator))?;
+    }
+    Ok(())
+}
+
+fn write_table<W: Write>(out: &mut W, events: &[Event], columns: &[Column]) -> io::Result<()> {
+    let header: Vec<String> = columns
+        .iter()
+        .map(|c| c.to_string().to_uppercase())
+        .collect();
+    let rows: Vec<Vec<String>> = events
+        .iter()
+        .map(|event| {
+            columns
+                .iter()
+                .map(|c| c.text(event).replace('\n', " "))
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..columns.len())
+        .map(|i| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(header[i].len()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    for row in Some(&header).into_iter().chain(rows.iter()) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(out, "{}", cells.join(" ").trim_end())?;
+    }
+    Ok(())
+}
+
+fn json_object(event: &Event, columns: Option<&[Column]>) -> Result<Value> {
+    Ok(match columns {
+        Some(columns) => Value::Object(
+            columns
+                .iter()
+                .map(|c| (c.to_string(), c.json(event)))
+                .collect::<Map<_, _>>(),
+        ),
+        None => serde_json::to_value(event)?,
+    })
+}
+
+/// Write events in a format.
+///
+/// Without `columns`, tables and delimited text use `DEFAULT_COLUMNS` and
+/// JSON contains every field of each event, as in Riemann's JSON format.
+pub fn write<W: Write>(
+    out: &mut W,
+    events: &[Event],
+    format: Format,
+    columns: Option<&[Column]>,
+) -> Result<()> {
+    let text_columns = columns.unwrap_or(&DEFAULT_COLUMNS);
+    match format {
+        Format::Table => write_table(out, events, text_columns)?,
+        Format::Csv => write_rows(out, events, text_columns, ",", csv_field)?,
+        Format::Tsv => write_rows(out, events, text_columns, "\t", tsv_field)?,
+        Format::Json => {
+            let objects = events
+                .iter()
+                .map(|event| json_object(event, columns))
+                .collect::<Result<Vec<_>>>()?;
+            serde_json::to_writer_pretty(&mut *out, &objects)?;
+            writeln!(out)?;
+        }
+        Format::Jsonl => {
+            for event in events {
+                serde_json::to_writer(&mut *out, &json_object(event, columns)?)?;
+                writeln!(out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::proto::{Attribute, Event};
+    use super::{parse_columns, write, Column, Format};
+    use serde_json::{json, Value};
+
+    fn events() -> Vec<Event> {
+        let mut first = Event::new();
+        first.set_host("web01".to_string());
+        first.set_service("cpu".to_string());
+        first.set_state("ok".to_string());
+        first.set_metric_d(0.25);
+        first.set_time(1700000000);
+        first.set_tags(vec!["prod".to_string()].into());
+        let mut attribute = Attribute::new();
+        attribute.set_key("region".to_string());
+        attribute.set_value("eu".to_string());
+        first.set_attributes(vec![attribute].into());
+
+        let mut second = Event::new();
+        second.set_host("db01".to_string());
+        second.set_service("disk, /".to_string());
+        second.set_metric_sint64(3);
+        second.set_description("a\tb".to_string());
+        vec![first, second]
+    }
+
+    fn output(format: Format, columns: Option<&[Column]>) -> String {
+        let mut out = Vec::new();
+        write(&mut out, &events(), format, columns).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!(
+            parse_columns("host, metric,region").unwrap(),
+            vec![
+                Column::Host,
+                Column::Metric,
+                Column::Attribute("region".to_string())
+            ]
+        );
+        assert!(parse_columns("host,,state").is_err());
+        assert!("yaml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn table() {
+        assert_eq!(
+            output(Format::Table, None),
+            "HOST  TIME       SERVICE METRIC STATE\n\
+             web01 1700000000 cpu     0.25   ok\n\
+             db01             disk, / 3\n"
+        );
+
+        let mut event = Event::new();
+        event.set_time_micros(1_700_000_000_500_000);
+        assert_eq!(Column::Time.text(&event), "1700000000.5");
+        event.set_time_micros(1_700_000_000_000_000);
+        assert_eq!(Column::Time.text(&event), "1700000000");
+    }
+
+    #[test]
+    fn delimited() {
+        let columns = parse_columns("service,metric,description,tags,region").unwrap();
+        assert_eq!(
+            output(Format::Csv, Some(&columns)),
+            "service,metric,description,tags,region\n\
+             cpu,0.25,,prod,eu\n\
+             \"disk, /\",3,a\tb,,\n"
+        );
+        assert_eq!(
+            output(Format::Tsv, Some(&columns)),
+            "service\tmetric\tdescription\ttags\tregion\n\
+             cpu\t0.25\t\tprod\teu\n\
+             disk, /\t3\ta\\tb\t\t\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let columns = parse_columns("host,metric,time,attributes").unwrap();
+        let lines: Vec<Value> = output(Format::Jsonl, Some(&columns))
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                json!({
+                    "host": "web01",
+                    "metric": 0.25,
+                    "time": "2023-11-14T22:13:20.000Z",
+                    "attributes": {"region": "eu"},
+                }),
+                json!({"host": "db01", "metric": 3, "time": null, "attributes": {}}),
+            ]
+        );
+
+        let all: Value = serde_json::from_str(&output(Format::Json, None)).unwrap();
+        assert_eq!(all[0]["service"], "cpu");
+        assert_eq!(all[0]["region"], "eu");
+        assert_eq!(all[1]["description"], "a\tb");
+    }
+}
@@ -0,0 +1,235 @@
+//! A server for Graphite's plaintext protocol that forwards metrics to Riemann.
+//!
+//! Each line, in the format `path value timestamp`, becomes an event with the
+//! value as `metric_d`. A `Mapping` describes which segments of the dotted
+//! path are used as the host, the service and tags.
+
+use std::fmt;
+use std::io::{BufRead, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
+
+use super::client::BatchClient;
+use super::proto::Event;
+use super::utils::{now_micros, Error};
+use super::Result;
+
+/// How a segment of a metric path is used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Segment {
+    Host,
+    Service,
+    Tag,
+    Ignore,
+}
+
+/// The role of each segment of a metric path, in order.
+///
+/// A mapping is written as the roles separated by dots, using `host`,
+/// `service`, `tag` and `-` for segments that are ignored. Segments past
+/// the end of the mapping are part of the service, so the empty mapping uses
+/// the whole path as the service. For example, `-.host` maps
+/// `servers.web01.cpu.load` to the host `web01` and the service `cpu.load`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Mapping {
+    pub segments: Vec<Segment>,
+}
+
+impl Mapping {
+    /// Convert a metric path into an event's host, service and tags.
+    pub fn apply(&self, path: &str, event: &mut Event) {
+        let mut service = Vec::new();
+        let mut tags = Vec::new();
+
+        for (index, segment) in path.split('.').enumerate() {
+            match self.segments.get(index).unwrap_or(&Segment::Service) {
+                Segment::Host => event.set_host(segment.to_string()),
+                Segment::Service => service.push(segment),
+                Segment::Tag => tags.push(segment.to_string()),
+                Segment::Ignore => {}
+            }
+        }
+
+        event.set_service(service.join("."));
+        if !tags.is_empty() {
+            event.set_tags(tags.into());
+        }
+    }
+}
+
+impl FromStr for Mapping {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.is_empty() {
+            return Ok(Mapping::default());
+        }
+        let segments = s
+            .split('.')
+            .map(|role| match role {
+                "host" => Ok(Segment::Host),
+                "service" => Ok(Segment::Service),
+                "tag" => Ok(Segment::Tag),
+                "-" => Ok(Segment::Ignore),
+                role => Err(Error::Parse(format!(
+                    "Invalid segment {:?} in mapping {:?}",
+                    role, s
+                ))),
+            })
+            .collect::<Result<_>>()?;
+        Ok(Mapping { segments })
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let roles: Vec<&str> = self
+            .segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Host => "host",
+                Segment::Service => "service",
+                Segment::Tag => "tag",
+                Segment::Ignore => "-",
+            })
+            .collect();
+        write!(f, "{}", roles.join("."))
+    }
+}
+
+/// Parse a `path value timestamp` line into an event. A negative timestamp
+/// means the current time, as in Graphite.
+pub fn parse_line(line: &str, mapping: &Mapping) -> Option<Event> {
+    let mut fields = line.split_whitespace();
+    let path = fields.next()?;
+    let value: f64 = fields.next()?.parse().ok()?;
+    let timestamp: f64 = fields.next()?.parse().ok()?;
+    if fields.next().is_some() {
+        return None;
+    }
+
+    let mut event = Event::new();
+    mapping.apply(path, &mut event);
+    event.set_metric_d(value);
+    event.set_time(if timestamp < 0.0 {
+        now_micros() / 1_000_000
+    } else {
+        timestamp as i64
+    });
+    Some(event)
+}
+
+/// Accepts Graphite plaintext connections and forwards their metrics to Riemann.
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    mapping: Mapping,
+}
+
+fn handle(stream: TcpStream, mapping: &Mapping, client: &BatchClient) {
+    for line in BufReader::new(stream).lines() {
+        match line {
+            Ok(line) => {
+                if let Some(event) = parse_line(&line, mapping) {
+                    client.send(event);
+                }
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+impl Server {
+    /// Listen for connections on a TCP address.
+    pub fn bind<A: ToSocketAddrs>(addr: A, mapping: Mapping) -> Result<Self> {
+        Ok(Server {
+            listener: TcpListener::bind(addr)?,
+            mapping,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept connections, sending the events from each through `client`
+    /// in batches, until accepting a connection fails. Lines that can't be
+    /// parsed are skipped.
+    pub fn run(self, client: BatchClient) -> Result<()> {
+        let client = Arc::new(client);
+        let mapping = Arc::new(self.mapping);
+
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let client = client.clone();
+            let mapping = mapping.clone();
+            thread::spawn(move || handle(stream, &mapping, &client));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::thread;
+
+    use super::super::client::test_server::TestServer;
+    use super::super::client::{BatchClient, BatchOptions};
+    use super::{parse_line, Mapping, Segment, Server};
+
+    #[test]
+    fn parse_mapping() {
+        let mapping: Mapping = "-.host.tag".parse().unwrap();
+        assert_eq!(
+            mapping.segments,
+            vec![Segment::Ignore, Segment::Host, Segment::Tag]
+        );
+        assert_eq!(mapping.to_string(), "-.host.tag");
+        assert_eq!("".parse::<Mapping>().unwrap(), Mapping::default());
+        assert!("host.region".parse::<Mapping>().is_err());
+    }
+
+    #[test]
+    fn parse_lines() {
+        let event =
+            parse_line("servers.web01.cpu.load 0.5 1700000000", &Mapping::default()).unwrap();
+        assert_eq!(event.get_service(), "servers.web01.cpu.load");
+        assert!(!event.has_host());
+        assert_eq!(event.get_metric_d(), 0.5);
+        assert_eq!(event.get_time(), 1700000000);
+
+        let mapping = "-.host.tag".parse().unwrap();
+        let event = parse_line("servers.web01.prod.cpu.load 2 -1", &mapping).unwrap();
+        assert_eq!(event.get_host(), "web01");
+        assert_eq!(event.get_service(), "cpu.load");
+        assert_eq!(event.get_tags(), ["prod"]);
+        assert!(event.get_time() > 1700000000);
+
+        assert!(parse_line("cpu.load", &mapping).is_none());
+        assert!(parse_line("cpu.load x 1700000000", &mapping).is_none());
+    }
+
+    #[test]
+    fn forward() {
+        let riemann = TestServer::start();
+        let server = Server::bind("127.0.0.1:0", "-.host".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let client = BatchClient::new(riemann.connector(), BatchOptions::default());
+        thread::spawn(move || server.run(client));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"servers.web01.load 1.5 1700000000\nnot a metric\n")
+            .unwrap();
+        drop(stream);
+
+        let events = riemann.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_host(), "web01");
+        assert_eq!(events[0].get_service(), "load");
+    }
+}
@@ -0,0 +1,196 @@
+//! A minimal blocking HTTP/1.1 client, used to read from Riemann's HTTP endpoints
+//! and Prometheus exporters.
+
+use std::cmp::min;
+use std::io::{BufRead, BufReader, Error as IoError, ErrorKind, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use super::utils::{Error, Result};
+
+/// The status and headers of a response, with a reader over the decoded body.
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Box<dyn BufRead + Send>,
+}
+
+impl Response {
+    /// Returns the value of the first header with the given (case insensitive) name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Send a `GET` request, failing if the response status is not `200`.
+pub fn get(
+    host: &str,
+    port: u16,
+    path: &str,
+    accept: &str,
+    timeout: Option<Duration>,
+) -> Result<Response> {
+    let mut stream = TcpStream::connect((host, port))?;
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: {}:{}\r\nAccept: {}\r\nConnection: close\r\n\r\n",
+        path, host, port, accept
+    )?;
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| Error::Http(format!("Invalid status line: {:?}", line.trim_end())))?;
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(Error::Http("Connection closed in headers".to_string()));
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(index) = header.find(':') {
+            headers.push((
+                header[..index].trim().to_string(),
+                header[index + 1..].trim().to_string(),
+            ));
+        }
+    }
+
+    let mut response = Response {
+        status,
+        headers,
+        body: Box::new(BufReader::new(::std::io::empty())),
+    };
+
+    if response.status != 200 {
+        return Err(Error::Http(format!(
+            "GET {} returned status {}",
+            path, response.status
+        )));
+    }
+
+    let chunked = response
+        .header("Transfer-Encoding")
+        .is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+    let length = response
+        .header("Content-Length")
+        .and_then(|value| value.parse::<u64>().ok());
+
+    response.body = if chunked {
+        Box::new(BufReader::new(Chunked::new(reader)))
+    } else if let Some(length) = length {
+        Box::new(reader.take(length))
+    } else {
+        Box::new(reader)
+    };
+
+    Ok(response)
+}
+
+/// Percent-encode a string for use in a URL query parameter.
+#[cfg_attr(not(feature = "subscribe"), allow(dead_code))]
+pub fn encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            byte => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked`.
+struct Chunked<R> {
+    inner: R,
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> Chunked<R> {
+    fn new(inner: R) -> Self {
+        Chunked {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for Chunked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+            let size = line.split(';').next().unwrap_or("").trim();
+            self.remaining = usize::from_str_radix(size, 16).map_err(|_| {
+                IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid chunk size {:?}", size),
+                )
+            })?;
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let length = min(buf.len(), self.remaining);
+        let read = self.inner.read(&mut buf[..length])?;
+        if read == 0 {
+            return Err(IoError::new(
+                ErrorKind::UnexpectedEof,
+                "Connection closed in chunk",
+            ));
+        }
+
+        self.remaining -= read;
+        if self.remaining == 0 {
+            let mut line = String::new();
+            self.inner.read_line(&mut line)?;
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::{encode, Chunked};
+
+    #[test]
+    fn decode_chunked() {
+        let body: &[u8] = b"5\r\nhello\r\n7;ext=1\r\n, world\r\n0\r\n\r\n";
+        let mut decoded = String::new();
+        Chunked::new(body).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "hello, world");
+    }
+
+    #[test]
+    fn encode_query() {
+        assert_eq!(encode("service = \"a/b\""), "service%20%3D%20%22a%2Fb%22");
+    }
+}
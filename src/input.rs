@@ -0,0 +1,358 @@
+//! Read events from text, one event per line.
+//!
+//! A line starting with `{` is an event in Riemann's JSON format, which
+//! requires the `serde` feature. Any other line is a list of `key=value`
+//! pairs separated by whitespace, where values containing spaces are double
+//! quoted:
+//!
+//! ```text
+//! service=backup state=ok metric=12.5 tags=cron,nightly description="all done"
+//! ```
+//!
+//! The keys `host`, `service`, `state`, `description`, `metric`, `ttl`,
+//! `time` (in unix seconds) and `tags` (comma separated) set those fields,
+//! and any other key is an attribute. Blank lines and lines starting with `#`
+//! are skipped.
+
+use std::io::{self, BufRead};
+use std::sync::mpsc::{sync_channel, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::client::Client;
+use super::proto::{Attribute, Event};
+use super::utils::Error;
+use super::Result;
+
+/// Split a line into `key=value` pairs, removing quotes and escapes from values.
+fn pairs(line: &str) -> Result<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(pairs);
+        }
+
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some('=') => break,
+                Some(c) if !c.is_whitespace() => key.push(c),
+                _ => return Err(Error::Parse(format!("expected '=' after {:?}", key))),
+            }
+        }
+
+        let unterminated = || Error::Parse(format!("unterminated value for {:?}", key));
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next().ok_or_else(unterminated)? {
+                    '"' => break,
+                    '\\' => match chars.next().ok_or_else(unterminated)? {
+                        'n' => value.push('\n'),
+                        c => value.push(c),
+                    },
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                value.push(c);
+            }
+        }
+
+        if key.is_empty() {
+            return Err(Error::Parse("expected a key before '='".to_string()));
+        }
+        pairs.push((key, value));
+    }
+}
+
+fn number<T: std::str::FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| Error::Parse(format!("expected '{}' to be a number", key)))
+}
+
+/// Parse a line of `key=value` pairs into an event.
+fn parse_pairs(line: &str) -> Result<Event> {
+    let mut event = Event::new();
+    let mut attributes = Vec::new();
+
+    for (key, value) in pairs(line)? {
+        match key.as_str() {
+            "host" => event.set_host(value),
+            "service" => event.set_service(value),
+            "state" => event.set_state(value),
+            "description" => event.set_description(value),
+            "metric" => match value.parse::<i64>() {
+                Ok(metric) => event.set_metric_sint64(metric),
+                Err(_) => {
+                    let metric: f64 = number(&key, &value)?;
+                    event.set_metric_d(metric);
+                    event.set_metric_f(metric as f32);
+                }
+            },
+            "ttl" => event.set_ttl(number(&key, &value)?),
+            "time" => {
+                let micros = (number::<f64>(&key, &value)? * 1e6) as i64;
+                event.set_time(micros.div_euclid(1_000_000));
+                if micros % 1_000_000 != 0 {
+                    event.set_time_micros(micros);
+                }
+            }
+            "tags" => event.set_tags(
+                value
+                    .split(',')
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            _ => {
+                let mut attribute = Attribute::new();
+                attribute.set_key(key);
+                attribute.set_value(value);
+                attributes.push(attribute);
+            }
+        }
+    }
+
+    if !attributes.is_empty() {
+        event.set_attributes(attributes.into());
+    }
+    Ok(event)
+}
+
+#[cfg(feature = "serde")]
+fn parse_json(line: &str) -> Result<Event> {
+    Ok(serde_json::from_str(line)?)
+}
+
+#[cfg(not(feature = "serde"))]
+fn parse_json(_line: &str) -> Result<Event> {
+    Err(Error::Parse(
+        "JSON events require the serde feature".to_string(),
+    ))
+}
+
+/// Parse a line into an event, returning `None` for blank lines and comments.
+pub fn parse_line(line: &str) -> Result<Option<Event>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        Ok(None)
+    } else if line.starts_with('{') {
+        parse_json(line).map(Some)
+    } else {
+        parse_pairs(line).map(Some)
+    }
+}
+
+/// The number of events and invalid lines read by `send`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Summary {
+    pub events: usize,
+    pub errors: usize,
+}
+
+/// Send a batch of events, if there are any.
+fn flush(client: &mut Client, batch: &mut Vec<Event>, summary: &mut Summary) -> Result<()> {
+    if !batch.is_empty() {
+        summary.events += batch.len();
+        client.events(std::mem::take(batch))?;
+    }
+    Ok(())
+}
+
+/// Read events from `reader` and send them with `client`, in batches of up
+/// to `batch_size` events. A batch is also sent once its first event has
+/// waited for `interval`, so events from a slow stream aren't held back.
+///
+/// Lines that can't be parsed, including lines that aren't valid UTF-8, are
+/// passed to `on_error` with their line number, starting at 1, and skipped.
+/// Reading or sending failures stop the stream and are returned.
+pub fn send<R, F>(
+    reader: R,
+    client: &mut Client,
+    batch_size: usize,
+    interval: Duration,
+    mut on_error: F,
+) -> Result<Summary>
+where
+    R: BufRead + Send + 'static,
+    F: FnMut(usize, Error),
+{
+    let batch_size = batch_size.max(1);
+    let mut summary = Summary::default();
+    let mut batch = Vec::with_capacity(batch_size);
+
+    // Read on another thread, so a batch can be sent while waiting for a line.
+    let (lines, received) = sync_channel::<io::Result<Vec<u8>>>(batch_size);
+    thread::spawn(move || {
+        let mut reader = reader;
+        loop {
+            let mut line = Vec::new();
+            let result = match reader.read_until(b'\n', &mut line) {
+                Ok(0) => return,
+                Ok(_) => Ok(line),
+                Err(e) => Err(e),
+            };
+            let failed = result.is_err();
+            if lines.send(result).is_err() || failed {
+                return;
+            }
+        }
+    });
+
+    let mut deadline: Option<Instant> = None;
+    let mut number = 0;
+    loop {
+        let line = match deadline {
+            Some(at) => match received.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    flush(client, &mut batch, &mut summary)?;
+                    deadline = None;
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match received.recv() {
+                Ok(line) => line,
+                Err(_) => break,
+            },
+        };
+
+        number += 1;
+        let parsed = String::from_utf8(line?)
+            .map_err(|_| Error::Parse("invalid UTF-8".to_string()))
+            .and_then(|line| parse_line(&line));
+        match parsed {
+            Ok(Some(event)) => batch.push(event),
+            Ok(None) => {}
+            Err(e) => {
+                summary.errors += 1;
+                on_error(number, e);
+            }
+        }
+
+        if batch.len() >= batch_size {
+            flush(client, &mut batch, &mut summary)?;
+        }
+        if batch.is_empty() {
+            deadline = None;
+        } else if deadline.is_none() {
+            deadline = Some(Instant::now() + interval);
+        }
+    }
+
+    flush(client, &mut batch, &mut summary)?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::super::client::test_server::TestServer;
+    use super::super::proto::Event;
+    use super::{parse_line, send, Summary};
+
+    #[test]
+    fn parse_pairs() {
+        let event = parse_line(
+            r#"service=backup state=ok metric=12.5 tags=cron,nightly description="all \"done\"" job=db"#,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(event.get_service(), "backup");
+        assert_eq!(event.get_state(), "ok");
+        assert_eq!(event.get_metric_d(), 12.5);
+        assert_eq!(event.get_tags(), ["cron", "nightly"]);
+        assert_eq!(event.get_description(), "all \"done\"");
+        assert_eq!(event.get_attributes()[0].get_key(), "job");
+        assert_eq!(event.get_attributes()[0].get_value(), "db");
+
+        let event = parse_line("metric=3 time=1700000000.5 ttl=60")
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.get_metric_sint64(), 3);
+        assert_eq!(event.get_time(), 1700000000);
+        assert_eq!(event.get_time_micros(), 1700000000500000);
+        assert_eq!(event.get_ttl(), 60.0);
+
+        assert!(parse_line("  ").unwrap().is_none());
+        assert!(parse_line("# comment").unwrap().is_none());
+        assert!(parse_line("metric=x").is_err());
+        assert!(parse_line("service").is_err());
+        assert!(parse_line("description=\"open").is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn parse_json() {
+        let event = parse_line(r#"{"service": "backup", "metric": 2}"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.get_service(), "backup");
+        assert_eq!(event.get_metric_sint64(), 2);
+        assert!(parse_line("{\"service\": 1}").is_err());
+    }
+
+    #[test]
+    fn send_lines() {
+        let server = TestServer::start();
+        let mut client = (server.connector())().unwrap();
+        let mut errors = Vec::new();
+
+        let input: &[u8] = b"service=a\nmetric=x\n\nservice=b\nservice=\xff\nservice=c\n";
+        let summary = send(input, &mut client, 2, Duration::from_secs(60), |line, _| {
+            errors.push(line)
+        })
+        .unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                events: 3,
+                errors: 2
+            }
+        );
+        assert_eq!(errors, vec![2, 5]);
+
+        let services = |events: Vec<Event>| -> Vec<String> {
+            events.iter().map(|e| e.get_service().to_string()).collect()
+        };
+        assert_eq!(services(server.events()), vec!["a", "b"]);
+        assert_eq!(services(server.events()), vec!["c"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn send_slow_stream() {
+        use std::io::{BufReader, Write};
+        use std::os::unix::net::UnixStream;
+        use std::thread;
+
+        let server = TestServer::start();
+        let mut client = (server.connector())().unwrap();
+        let (mut writer, reader) = UnixStream::pair().unwrap();
+
+        let sender = thread::spawn(move || {
+            let reader = BufReader::new(reader);
+            send(
+                reader,
+                &mut client,
+                100,
+                Duration::from_millis(50),
+                |_, _| {},
+            )
+            .unwrap()
+        });
+        writer.write_all(b"service=a\n").unwrap();
+        assert_eq!(server.events()[0].get_service(), "a");
+
+        drop(writer);
+        assert_eq!(sender.join().unwrap().events, 1);
+    }
+}
@@ -0,0 +1,303 @@
+//! Serialization of events to and from Riemann's JSON event format.
+//!
+//! This is the shape used by the websocket, SSE and HTTP interfaces: the
+//! standard fields as top-level keys, `time` as an ISO-8601 string and any
+//! custom attributes as additional top-level keys.
+
+use serde::de::{Deserialize, Deserializer, Error as _};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::{Map, Value};
+
+use super::proto::{Attribute, Event, Metric};
+
+/// Keys that hold standard event fields rather than attributes.
+const FIELDS: [&str; 9] = [
+    "host",
+    "service",
+    "state",
+    "description",
+    "metric",
+    "tags",
+    "time",
+    "ttl",
+    "time_micros",
+];
+
+impl Serialize for Event {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+
+        if self.has_host() {
+            map.serialize_entry("host", self.get_host())?;
+        }
+        if self.has_service() {
+            map.serialize_entry("service", self.get_service())?;
+        }
+        if self.has_state() {
+            map.serialize_entry("state", self.get_state())?;
+        }
+        if self.has_description() {
+            map.serialize_entry("description", self.get_description())?;
+        }
+
+        match self.metric() {
+            Some(Metric::Int(metric)) => map.serialize_entry("metric", &metric)?,
+            Some(metric) => map.serialize_entry("metric", &metric.as_f64())?,
+            None => {}
+        }
+
+        if !self.get_tags().is_empty() {
+            map.serialize_entry("tags", self.get_tags())?;
+        }
+
+        if self.has_time_micros() {
+            map.serialize_entry("time", &format_time(self.get_time_micros()))?;
+        } else if self.has_time() {
+            map.serialize_entry("time", &format_time(self.get_time() * 1_000_000))?;
+        }
+
+        if self.has_ttl() {
+            map.serialize_entry("ttl", &self.get_ttl())?;
+        }
+
+        for attribute in self.get_attributes() {
+            if !FIELDS.contains(&attribute.get_key()) {
+                map.serialize_entry(attribute.get_key(), attribute.get_value())?;
+            }
+        }
+
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let map = Map::<String, Value>::deserialize(deserializer)?;
+        event_from_map(map).map_err(D::Error::custom)
+    }
+}
+
+fn string(value: Value, key: &str) -> Result<String, String> {
+    match value {
+        Value::String(string) => Ok(string),
+        _ => Err(format!("expected '{}' to be a string", key)),
+    }
+}
+
+fn event_from_map(map: Map<String, Value>) -> Result<Event, String> {
+    let mut event = Event::new();
+    let mut attributes = Vec::new();
+
+    for (key, value) in map {
+        if value.is_null() {
+            continue;
+        }
+
+        match key.as_str() {
+            "host" => event.set_host(string(value, &key)?),
+            "service" => event.set_service(string(value, &key)?),
+            "state" => event.set_state(string(value, &key)?),
+            "description" => event.set_description(string(value, &key)?),
+            "metric" => match value {
+                Value::Number(ref number) if number.is_i64() => {
+                    event.set_metric_sint64(number.as_i64().unwrap())
+                }
+                Value::Number(ref number) => {
+                    // Riemann sets both floating point fields when encoding a metric.
+                    let metric = number.as_f64().unwrap();
+                    event.set_metric_d(metric);
+                    event.set_metric_f(metric as f32);
+                }
+                _ => return Err("expected 'metric' to be a number".to_string()),
+            },
+            "ttl" => match value.as_f64() {
+                Some(ttl) => event.set_ttl(ttl as f32),
+                None => return Err("expected 'ttl' to be a number".to_string()),
+            },
+            "time" => {
+                let micros = match value {
+                    Value::String(ref time) => parse_time(time),
+                    Value::Number(ref number) => number.as_f64().map(|s| (s * 1e6) as i64),
+                    _ => None,
+                }
+                .ok_or("expected 'time' to be an ISO-8601 timestamp")?;
+                event.set_time(micros.div_euclid(1_000_000));
+                if micros % 1_000_000 != 0 {
+                    event.set_time_micros(micros);
+                }
+            }
+            "time_micros" => match value.as_i64() {
+                Some(micros) => event.set_time_micros(micros),
+                None => return Err("expected 'time_micros' to be an integer".to_string()),
+            },
+            "tags" => match value {
+                Value::Array(tags) => event.set_tags(
+                    tags.into_iter()
+                        .map(|tag| string(tag, "tags"))
+                        .collect::<Result<Vec<String>, String>>()?
+                        .into(),
+                ),
+                _ => return Err("expected 'tags' to be an array".to_string()),
+            },
+            _ => {
+                let mut attribute = Attribute::new();
+                attribute.set_value(match value {
+                    Value::String(string) => string,
+                    value => value.to_string(),
+                });
+                attribute.set_key(key);
+                attributes.push(attribute);
+            }
+        }
+    }
+
+    if !attributes.is_empty() {
+        event.set_attributes(attributes.into());
+    }
+
+    Ok(event)
+}
+
+/// Days since the unix epoch for a proleptic Gregorian calendar date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Proleptic Gregorian calendar date for a number of days since the unix epoch.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Format microseconds since the unix epoch as an ISO-8601 timestamp, with
+/// millisecond precision unless the time has a finer component.
+pub(crate) fn format_time(micros: i64) -> String {
+    let seconds = micros.div_euclid(1_000_000);
+    let fraction = micros.rem_euclid(1_000_000);
+    let (year, month, day) = civil_from_days(seconds.div_euclid(86_400));
+    let clock = seconds.rem_euclid(86_400);
+
+    let fraction = if fraction % 1000 == 0 {
+        format!("{:03}", fraction / 1000)
+    } else {
+        format!("{:06}", fraction)
+    };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{}Z",
+        year,
+        month,
+        day,
+        clock / 3600,
+        clock % 3600 / 60,
+        clock % 60,
+        fraction
+    )
+}
+
+/// Parse an ISO-8601 timestamp such as `2015-05-20T14:06:13.123Z` into
+/// microseconds since the unix epoch.
+fn parse_time(time: &str) -> Option<i64> {
+    fn number(s: &str) -> Option<i64> {
+        if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    let (date, rest) = time.split_at(time.find(['T', ' '])?);
+    let mut date = date.splitn(3, '-');
+    let year = number(date.next()?)?;
+    let month = number(date.next()?)?;
+    let day = number(date.next()?)?;
+
+    let rest = &rest[1..];
+    let zone_start = rest.find(['Z', 'z', '+', '-']).unwrap_or(rest.len());
+    let (clock, zone) = rest.split_at(zone_start);
+
+    let (clock, fraction) = match clock.find('.') {
+        Some(index) => (&clock[..index], &clock[index + 1..]),
+        None => (clock, ""),
+    };
+    let mut clock = clock.splitn(3, ':');
+    let hour = number(clock.next()?)?;
+    let minute = number(clock.next()?)?;
+    let second = clock.next().map_or(Some(0), number)?;
+
+    let micros = if fraction.is_empty() {
+        0
+    } else {
+        let digits: String = fraction.chars().chain("000000".chars()).take(6).collect();
+        number(&digits)?
+    };
+
+    let offset = match zone {
+        "" | "Z" | "z" => 0,
+        zone => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            let zone = zone[1..].replace(':', "");
+            if zone.len() != 4 {
+                return None;
+            }
+            sign * (number(zone.get(..2)?)? * 3600 + number(zone.get(2..)?)? * 60)
+        }
+    };
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+
+    let seconds =
+        days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset;
+    Some(seconds * 1_000_000 + micros)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_time, parse_time};
+
+    #[test]
+    fn time() {
+        assert_eq!(parse_time("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_time("2015-05-20T14:06:13.123Z"),
+            Some(1_432_130_773_123_000)
+        );
+        assert_eq!(
+            parse_time("2015-05-20T16:06:13.123456+02:00"),
+            Some(1_432_130_773_123_456)
+        );
+        assert_eq!(parse_time("1969-12-31T23:59:59Z"), Some(-1_000_000));
+        assert_eq!(parse_time("yesterday"), None);
+        assert_eq!(parse_time("2015-05-20T14:06:13+aé1"), None);
+    }
+
+    #[test]
+    fn time_format() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(
+            format_time(1_432_130_773_123_000),
+            "2015-05-20T14:06:13.123Z"
+        );
+        assert_eq!(
+            format_time(1_582_977_600_000_001),
+            "2020-02-29T12:00:00.000001Z"
+        );
+        assert_eq!(format_time(-1_000_000), "1969-12-31T23:59:59.000Z");
+    }
+}
@@ -1,9 +1,41 @@
 //! A [Riemann](http://riemann.io/) client library and command line interface.
 
+pub mod check;
 pub mod client;
+#[cfg(target_os = "linux")]
+pub mod collector;
+#[cfg(feature = "toml")]
+pub mod config;
+pub mod exec;
+#[cfg(feature = "serde")]
+pub mod format;
+pub mod graphite;
+mod http;
+pub mod input;
+#[cfg(feature = "serde")]
+mod json;
+#[cfg(feature = "log")]
+pub mod logger;
+#[cfg(feature = "opentelemetry")]
+pub mod otel;
+#[cfg(target_os = "linux")]
+pub mod process;
+pub mod prometheus;
 /// Layer one: Protobuf implementation generated by `protoc --rust_out`.
 pub mod proto;
+pub mod query;
+#[cfg(feature = "metrics")]
+pub mod recorder;
+pub mod registry;
+#[cfg(feature = "serde")]
+pub mod repl;
+pub mod statsd;
+#[cfg(feature = "subscribe")]
+pub mod subscribe;
+#[cfg(feature = "tracing")]
+pub mod trace;
 pub mod transport;
+pub mod watch;
 
 pub use self::client::Client;
 pub use self::utils::{Error, Result};
@@ -12,10 +44,12 @@ pub use self::utils::{Error, Result};
 mod utils {
     use std::fmt::{Display, Formatter};
     use std::io::Error as IoError;
+    use std::time::{SystemTime, UNIX_EPOCH};
 
     use ::protobuf::error::ProtobufError;
 
     use super::proto::Query;
+    use super::query::ParseError;
 
     impl<'a> From<&'a str> for Query {
         fn from(query_str: &'a str) -> Self {
@@ -41,11 +75,52 @@ mod utils {
         Key(String),
         TLS(rustls::TLSError),
         InvalidDNSNameError(webpki::InvalidDNSNameError),
+        Query(ParseError),
+        Http(String),
+        Parse(String),
+        #[cfg(feature = "serde")]
+        Json(serde_json::Error),
+        #[cfg(feature = "subscribe")]
+        WebSocket(Box<tungstenite::Error>),
     }
 
     impl Display for Error {
         fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
-            write!(f, "{}", self)
+            match *self {
+                Error::Io(ref e) => write!(f, "{}", e),
+                Error::Protobuf(ref e) => write!(f, "Protobuf error: {}", e),
+                Error::Riemann(ref e) => write!(f, "Riemann server error: {}", e),
+                Error::Cert(ref e) => write!(f, "Certificate error: {}", e),
+                Error::CACert(ref e) => write!(f, "CA certificate error: {}", e),
+                Error::Key(ref e) => write!(f, "Key error: {}", e),
+                Error::TLS(ref e) => write!(f, "TLS error: {}", e),
+                Error::InvalidDNSNameError(_) => write!(f, "Invalid DNS name for TLS"),
+                Error::Query(ref e) => write!(f, "Invalid query: {}", e),
+                Error::Http(ref e) => write!(f, "HTTP error: {}", e),
+                Error::Parse(ref e) => write!(f, "{}", e),
+                #[cfg(feature = "serde")]
+                Error::Json(ref e) => write!(f, "JSON error: {}", e),
+                #[cfg(feature = "subscribe")]
+                Error::WebSocket(ref e) => write!(f, "WebSocket error: {}", e),
+            }
+        }
+    }
+
+    impl ::std::error::Error for Error {
+        fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
+            match *self {
+                Error::Io(ref e) => Some(e),
+                Error::Protobuf(ref e) => Some(e),
+                Error::Cert(ref e) => Some(e),
+                Error::TLS(ref e) => Some(e),
+                Error::InvalidDNSNameError(ref e) => Some(e),
+                Error::Query(ref e) => Some(e),
+                #[cfg(feature = "serde")]
+                Error::Json(ref e) => Some(e),
+                #[cfg(feature = "subscribe")]
+                Error::WebSocket(ref e) => Some(e.as_ref()),
+                _ => None,
+            }
         }
     }
 
@@ -79,6 +154,53 @@ mod utils {
         }
     }
 
+    impl From<ParseError> for Error {
+        fn from(err: ParseError) -> Self {
+            Error::Query(err)
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    impl From<serde_json::Error> for Error {
+        fn from(err: serde_json::Error) -> Self {
+            Error::Json(err)
+        }
+    }
+
+    #[cfg(feature = "subscribe")]
+    impl From<tungstenite::Error> for Error {
+        fn from(err: tungstenite::Error) -> Self {
+            Error::WebSocket(Box::new(err))
+        }
+    }
+
     /// Result alias for Riemann client errors
     pub type Result<T> = ::std::result::Result<T, Error>;
+
+    /// The current time in microseconds since the unix epoch.
+    #[allow(dead_code)]
+    pub(crate) fn now_micros() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as i64)
+    }
+
+    #[cfg(test)]
+    mod test {
+        use std::error::Error as _;
+        use std::io::{Error as IoError, ErrorKind};
+
+        use super::Error;
+
+        #[test]
+        fn display() {
+            let error = Error::from(IoError::new(ErrorKind::ConnectionRefused, "refused"));
+            assert_eq!(error.to_string(), "refused");
+            assert!(error.source().is_some());
+
+            let error = Error::Riemann("parse error".to_string());
+            assert_eq!(error.to_string(), "Riemann server error: parse error");
+            assert!(error.source().is_none());
+        }
+    }
 }
@@ -0,0 +1,210 @@
+//! A `log` backend that sends log records to Riemann as events.
+//!
+//! Each record becomes an event with the record's target as the service, a
+//! state derived from its level and the message as the description. Events
+//! are sent through a `BatchClient`, so logging only blocks on the network
+//! when the logger is flushed.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ::log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use super::client::BatchClient;
+use super::proto::{Attribute, Event};
+use super::utils::now_micros;
+
+/// A logger that sends records at or above a level to Riemann.
+pub struct Logger {
+    client: BatchClient,
+    level: LevelFilter,
+    rate_limit: Option<u32>,
+    window: Mutex<(Instant, u32)>,
+}
+
+fn attribute(key: &str, value: String) -> Attribute {
+    let mut attribute = Attribute::new();
+    attribute.set_key(key.to_string());
+    attribute.set_value(value);
+    attribute
+}
+
+/// The event state for a log level.
+pub fn state(level: Level) -> &'static str {
+    match level {
+        Level::Error => "critical",
+        Level::Warn => "warning",
+        Level::Info | Level::Debug | Level::Trace => "ok",
+    }
+}
+
+/// Build an event from a log record.
+pub fn event_from_record(record: &Record) -> Event {
+    let mut event = Event::new();
+    let micros = now_micros();
+    event.set_time(micros / 1_000_000);
+    event.set_time_micros(micros);
+    event.set_service(record.target().to_string());
+    event.set_state(state(record.level()).to_string());
+    event.set_description(record.args().to_string());
+    event.set_tags(vec!["log".to_string()].into());
+
+    let mut attributes = vec![attribute("level", record.level().to_string())];
+    if let Some(module) = record.module_path() {
+        attributes.push(attribute("module", module.to_string()));
+    }
+    if let Some(file) = record.file() {
+        attributes.push(attribute("file", file.to_string()));
+    }
+    if let Some(line) = record.line() {
+        attributes.push(attribute("line", line.to_string()));
+    }
+    event.set_attributes(attributes.into());
+
+    event
+}
+
+impl Logger {
+    /// Create a logger that sends `Warn` and `Error` records through `client`.
+    pub fn new(client: BatchClient) -> Self {
+        Logger {
+            client,
+            level: LevelFilter::Warn,
+            rate_limit: None,
+            window: Mutex::new((Instant::now(), 0)),
+        }
+    }
+
+    /// Set the most verbose level that will be sent.
+    pub fn set_level(&mut self, level: LevelFilter) {
+        self.level = level;
+    }
+
+    /// Set the maximum number of records sent per second, discarding any others.
+    pub fn set_rate_limit(&mut self, per_second: Option<u32>) {
+        self.rate_limit = per_second;
+    }
+
+    /// Install this logger as the global logger.
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        let level = self.level;
+        ::log::set_boxed_logger(Box::new(self))?;
+        ::log::set_max_level(level);
+        Ok(())
+    }
+
+    /// Check the rate limit, counting this record against it if it is allowed.
+    fn allow(&self) -> bool {
+        let limit = match self.rate_limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        if now.duration_since(window.0) >= Duration::from_secs(1) {
+            *window = (now, 0);
+        }
+        if window.1 < limit {
+            window.1 += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) && self.allow() {
+            self.client.send(event_from_record(record));
+        }
+    }
+
+    /// Send queued records, waiting up to `FLUSH_TIMEOUT` for them to be sent.
+    fn flush(&self) {
+        self.client.flush();
+    }
+}
+
+impl ::std::fmt::Debug for Logger {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "Logger {{ level: {}, rate_limit: {:?} }}",
+            self.level, self.rate_limit
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ::log::{Level, LevelFilter, Log, Record};
+
+    use super::super::client::test_server::TestServer;
+    use super::super::client::{BatchClient, BatchOptions};
+    use super::{event_from_record, Logger};
+
+    fn record<F: FnOnce(&Record)>(level: Level, message: &str, f: F) {
+        f(&Record::builder()
+            .args(format_args!("{}", message))
+            .level(level)
+            .target("app::db")
+            .module_path(Some("app::db"))
+            .file(Some("src/db.rs"))
+            .line(Some(42))
+            .build())
+    }
+
+    #[test]
+    fn record_to_event() {
+        record(Level::Error, "connection lost", |record| {
+            let event = event_from_record(record);
+            assert_eq!(event.get_service(), "app::db");
+            assert_eq!(event.get_state(), "critical");
+            assert_eq!(event.get_description(), "connection lost");
+            assert!(event.has_time());
+
+            let attributes: Vec<(&str, &str)> = event
+                .get_attributes()
+                .iter()
+                .map(|a| (a.get_key(), a.get_value()))
+                .collect();
+            assert_eq!(
+                attributes,
+                vec![
+                    ("level", "ERROR"),
+                    ("module", "app::db"),
+                    ("file", "src/db.rs"),
+                    ("line", "42")
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn filter_and_limit() {
+        let server = TestServer::start();
+        let mut logger = Logger::new(BatchClient::new(
+            server.connector(),
+            BatchOptions::default(),
+        ));
+        logger.set_level(LevelFilter::Info);
+        logger.set_rate_limit(Some(2));
+
+        record(Level::Debug, "ignored", |r| logger.log(r));
+        record(Level::Info, "one", |r| logger.log(r));
+        record(Level::Warn, "two", |r| logger.log(r));
+        record(Level::Error, "limited", |r| logger.log(r));
+        logger.flush();
+
+        let events = server.events();
+        let descriptions: Vec<&str> = events.iter().map(|e| e.get_description()).collect();
+        assert_eq!(descriptions, vec!["one", "two"]);
+        assert_eq!(events[1].get_state(), "warning");
+    }
+}
@@ -1,32 +1,52 @@
 //! A command line interface to [Riemann](http://riemann.io/).
 //!
-//! Requires the optional dependencies (`docopt` and `serde`) that are
-//! included by the default feature.
+//! Requires the optional dependencies (`docopt`, `rustyline`, `serde` and
+//! `toml`) that are included by the default feature.
 
 #![cfg(not(test))]
 #![cfg(feature = "default")]
 
+use std::fmt::Display;
+use std::process::exit;
+
 use docopt::Docopt;
+use riemann_client::config::{Config, Profile};
+use riemann_client::Error;
 use serde::Deserialize;
 
 static USAGE: &str = "
-Usage: riemann_cli [-H <host> -P <port>] [--mtls --cafile <file> --cert <file> --key <file>] send [options]
-       riemann_cli [-H <host> -P <port>] [--mtls --cafile <file> --cert <file> --key <file>] query <query>
+Usage: riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] send [options]
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] query [--format <format> --columns <list>] <query>
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] watch [--interval <seconds> --subscribe] <query>
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] repl
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] health [--interval <seconds>]
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] scrape <source>
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] statsd [--listen <addr> --interval <seconds>]
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] graphite [--listen <addr> --mapping <pattern>]
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] check [--service <str> --ttl <f32>] [--] <command>...
+       riemann_cli [-H <host> -P <port> --profile <name> --config <file>] [--mtls | --no-mtls] [--cafile <file> --cert <file> --key <file>] exec --service <str> [--ttl <f32>] [--] <command>...
        riemann_cli --help | --version
 
 Server options:
-    -H, --server-host <host>    Riemann server hostname [default: localhost].
-    -P, --server-port <port>    Riemann server port [default: 5555].
+    -H, --server-host <host>    Riemann server hostname (defaults to localhost).
+    -P, --server-port <port>    Riemann server port (defaults to 5555).
     --mtls                      Connect to Riemann server using mTLS.
+    --no-mtls                   Connect without mTLS, even if the config or environment enables it.
     --cafile <file>             CA certificate filename.
     --cert <file>               Client certificate filename.
     --key <file>                Client key certificate filename.
+    --profile <name>            Use a profile from the config file (or $RIEMANN_PROFILE).
+    --config <file>             Config file (defaults to ~/.config/riemann-cli.toml).
+
+Server options override the RIEMANN_HOST, RIEMANN_PORT, RIEMANN_MTLS, RIEMANN_CAFILE,
+RIEMANN_CERT and RIEMANN_KEY environment variables, which override the config file.
 
 
 Event options:
     -T, --time <i64>            Event timestamp (unix format).
     -S, --state <str>           Event state.
-    -s, --service <str>         Event service [default: riemann-cli].
+    -s, --service <str>         Event service (defaults to riemann-cli, or the command's
+                                name for check).
     -N, --host <str>            Event hostname (defaults to current hostname).
     -d, --description <str>     Event description.
     -t, --tag <str>             Event tags (can be used multiple times).
@@ -35,16 +55,89 @@ Event options:
     -i, --metric-sint64 <i64>   Event metric as an integer (using the metric_sint64 field).
     -m, --metric-d <f64>        Event metric as a double (using the metric_d field).
     -f, --metric-f <f32>        Event metric as a float (using the metric_f field).
+    --input <file>              Send events read from a file, or stdin if '-', instead
+                                of from options. Each line is a JSON event or key=value pairs.
+
+Query options:
+    --format <format>           Output format: table, json, jsonl, csv or tsv [default: table].
+    --columns <list>            Comma separated fields or attribute names to output (defaults to
+                                host, time, service, metric and state, or all fields for JSON).
+    --subscribe                 Watch events as they arrive using Riemann's websocket server
+                                on port 5556, instead of querying every interval. The
+                                websocket is not encrypted, so this can't be used with mTLS.
+
+Health, StatsD and Graphite options:
+    --interval <seconds>        Seconds between sending metrics [default: 10].
+    --listen <addr>             Address to receive metrics on (defaults to 0.0.0.0:8125
+                                for StatsD and 0.0.0.0:2003 for Graphite).
+    --mapping <pattern>         Roles of Graphite path segments, e.g. '-.host.tag'.
+
+Exit status:
+    0 on success, 2 for usage errors, 3 if connecting to Riemann fails, 4 if setting
+    up TLS fails, 5 if Riemann returns an error and 1 for other errors. The exec
+    command exits with the status of the command it runs.
 ";
 
-#[derive(Deserialize, Debug)]
+/// Exit codes for failures, with 1 used for any other error.
+const EXIT_USAGE: i32 = 2;
+const EXIT_CONNECTION: i32 = 3;
+const EXIT_TLS: i32 = 4;
+const EXIT_SERVER: i32 = 5;
+
+/// An error message and the exit code for it.
+struct Failure {
+    code: i32,
+    message: String,
+}
+
+impl Failure {
+    fn new<T: Display>(code: i32, message: T) -> Self {
+        Failure {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    fn usage<T: Display>(message: T) -> Self {
+        Failure::new(EXIT_USAGE, message)
+    }
+
+    /// Add context to the message, such as what was being done when the error occurred.
+    fn context<T: Display>(self, context: T) -> Self {
+        Failure::new(self.code, format!("{}: {}", context, self.message))
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        let code = match error {
+            Error::Riemann(_) => EXIT_SERVER,
+            Error::Cert(_)
+            | Error::CACert(_)
+            | Error::Key(_)
+            | Error::TLS(_)
+            | Error::InvalidDNSNameError(_) => EXIT_TLS,
+            Error::Io(_) | Error::Protobuf(_) => EXIT_CONNECTION,
+            _ => 1,
+        };
+        Failure::new(code, error)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 struct Args {
-    flag_server_host: String,
-    flag_server_port: u16,
-    flag_mtls: Option<bool>,
+    flag_server_host: Option<String>,
+    flag_server_port: Option<u16>,
+    flag_mtls: bool,
+    flag_no_mtls: bool,
     flag_cafile: Option<String>,
     flag_cert: Option<String>,
     flag_key: Option<String>,
+    flag_profile: Option<String>,
+    flag_config: Option<String>,
+    /// Connection settings from the server options, environment and config file.
+    #[serde(skip)]
+    profile: Profile,
 
     cmd_send: bool,
     flag_time: Option<i64>,
@@ -58,47 +151,386 @@ struct Args {
     flag_metric_sint64: Option<i64>,
     flag_metric_d: Option<f64>,
     flag_metric_f: Option<f32>,
+    flag_input: Option<String>,
 
     cmd_query: bool,
     arg_query: String,
+    flag_format: String,
+    flag_columns: Option<String>,
+
+    cmd_watch: bool,
+    flag_subscribe: bool,
+
+    cmd_repl: bool,
+
+    cmd_health: bool,
+    flag_interval: u64,
+
+    cmd_scrape: bool,
+    arg_source: String,
+
+    cmd_statsd: bool,
+    flag_listen: Option<String>,
+
+    cmd_graphite: bool,
+    flag_mapping: Option<String>,
+
+    cmd_check: bool,
+    arg_command: Vec<String>,
+
+    cmd_exec: bool,
 
     flag_version: bool,
 }
 
-fn main() {
-    use riemann_client::proto::Attribute;
+/// Combine the server options, environment variables and config file, in
+/// that order of precedence.
+fn profile(args: &Args) -> riemann_client::Result<Profile> {
+    let flags = Profile {
+        host: args.flag_server_host.clone(),
+        port: args.flag_server_port,
+        mtls: match (args.flag_mtls, args.flag_no_mtls) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        cafile: args.flag_cafile.clone(),
+        cert: args.flag_cert.clone(),
+        key: args.flag_key.clone(),
+    };
+
+    let config = match args.flag_config {
+        Some(ref path) => Config::load(path)?,
+        None => match Config::default_path().filter(|path| path.exists()) {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        },
+    };
+    let name = args
+        .flag_profile
+        .clone()
+        .or_else(|| std::env::var("RIEMANN_PROFILE").ok());
+
+    Ok(flags
+        .or(Profile::from_env()?)
+        .or(config.profile(name.as_deref())?))
+}
+
+fn connect(args: &Args) -> riemann_client::Result<riemann_client::Client> {
+    args.profile.connect()
+}
+
+/// Send host metrics every interval, reconnecting if sending fails.
+#[cfg(target_os = "linux")]
+fn health(args: &Args) -> ! {
+    use riemann_client::collector::Collector;
+    use std::time::Duration;
+
+    let interval = Duration::from_secs(args.flag_interval);
+    let mut collector = Collector::default();
+    let mut client = None;
+
+    loop {
+        match collector.collect() {
+            Ok(mut events) => {
+                // Events expire if two intervals pass without an update.
+                for event in events.iter_mut() {
+                    event.set_ttl(interval.as_secs_f32() * 2.0);
+                }
+                if client.is_none() {
+                    client = connect(args)
+                        .map_err(|e| eprintln!("Failed to connect: {}", e))
+                        .ok();
+                }
+                if let Some(ref mut c) = client {
+                    if let Err(e) = c.events(events) {
+                        eprintln!("Failed to send host metrics: {}", e);
+                        client = None;
+                    }
+                }
+            }
+            Err(e) => eprintln!("Failed to collect host metrics: {}", e),
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn health(_args: &Args) -> ! {
+    eprintln!("Host metrics can only be collected on Linux.");
+    std::process::exit(1)
+}
+
+/// Run a command and send its duration and exit status, exiting with the
+/// command's exit code.
+fn exec(args: &Args) -> ! {
+    let service = args.flag_service.as_deref().unwrap_or_default();
+    let (mut event, code) = match riemann_client::exec::run(&args.arg_command) {
+        Ok(execution) => (execution.event(service), execution.exit_code()),
+        Err(e) => {
+            eprintln!("Failed to run command: {}", e);
+            let mut event = riemann_client::proto::Event::new();
+            event.set_service(service.to_string());
+            event.set_state("critical".to_string());
+            event.set_description(format!("Failed to run command: {}", e));
+            (event, 127)
+        }
+    };
+    if let Some(x) = args.flag_ttl {
+        event.set_ttl(x);
+    }
+
+    if let Err(e) = connect(args).and_then(|mut client| client.event(event)) {
+        eprintln!("Failed to send event: {}", e);
+    }
+    std::process::exit(code)
+}
+
+/// Redraw the results of a query every interval, reconnecting if the query fails.
+fn watch(args: &Args) -> ! {
+    use riemann_client::watch::Watch;
+    use std::io::IsTerminal;
+
+    let terminal = std::io::stdout().is_terminal();
+    let mut watch = Watch::new();
+    if args.flag_subscribe {
+        watch_subscription(args, watch, terminal);
+    }
+
+    let title = format!("Every {}s: {}", args.flag_interval, args.arg_query);
+    let mut client = None;
+    loop {
+        if client.is_none() {
+            client = connect(args)
+                .map_err(|e| eprintln!("Failed to connect: {}", e))
+                .ok();
+        }
+        if let Some(ref mut c) = client {
+            match c.query(args.arg_query.clone()) {
+                Ok(events) => {
+                    watch.update(events);
+                    let _ = watch.render(&mut std::io::stdout().lock(), &title, terminal);
+                }
+                Err(e) => {
+                    eprintln!("Failed to query: {}", e);
+                    client = None;
+                }
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_secs(args.flag_interval));
+    }
+}
+
+/// Redraw the results of a query as matching events arrive.
+#[cfg(feature = "subscribe")]
+fn watch_subscription(args: &Args, mut watch: riemann_client::watch::Watch, terminal: bool) -> ! {
+    use riemann_client::subscribe::{Subscription, WEBSOCKET_PORT};
 
+    let title = format!("Subscribed: {}", args.arg_query);
+    match connect(args).and_then(|mut client| client.query(args.arg_query.clone())) {
+        Ok(events) => watch.update(events),
+        Err(e) => eprintln!("Failed to query: {}", e),
+    }
+    let _ = watch.render(&mut std::io::stdout().lock(), &title, terminal);
+
+    let subscription =
+        Subscription::websocket(args.profile.host(), WEBSOCKET_PORT, args.arg_query.clone());
+    for event in subscription {
+        match event {
+            Ok(event) => {
+                watch.insert(event);
+                let _ = watch.render(&mut std::io::stdout().lock(), &title, terminal);
+            }
+            Err(e) => eprintln!("Subscription failed: {}", e),
+        }
+    }
+    std::process::exit(1)
+}
+
+#[cfg(not(feature = "subscribe"))]
+fn watch_subscription(_args: &Args, _watch: riemann_client::watch::Watch, _terminal: bool) -> ! {
+    eprintln!("Subscribing requires the subscribe feature.");
+    std::process::exit(1)
+}
+
+/// Read queries and meta-commands from the terminal, keeping history in
+/// `~/.riemann_cli_history`.
+fn repl(args: &Args) -> Result<(), Failure> {
+    use riemann_client::repl::Session;
+    use rustyline::error::ReadlineError;
+
+    let history = std::env::var_os("HOME")
+        .map(|home| std::path::Path::new(&home).join(".riemann_cli_history"));
+    let mut editor = rustyline::DefaultEditor::new()
+        .map_err(|e| Failure::new(1, format!("Failed to start the REPL: {}", e)))?;
+    if let Some(ref history) = history {
+        let _ = editor.load_history(history);
+    }
+
+    println!(
+        "Querying {}:{}, enter :help for a list of commands.",
+        args.profile.host(),
+        args.profile.port()
+    );
+    let mut session = Session::new(|| connect(args));
+    loop {
+        match editor.readline("riemann> ") {
+            Ok(line) => {
+                let _ = editor.add_history_entry(line.as_str());
+                match session.run(&line, &mut std::io::stdout().lock()) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => eprintln!("Error: {}", e),
+                }
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("Failed to read input: {}", e);
+                break;
+            }
+        }
+    }
+
+    if let Some(ref history) = history {
+        let _ = editor.save_history(history);
+    }
+    Ok(())
+}
+
+/// Send events read from a file, or stdin if the path is `-`, one per line.
+fn send_input(client: &mut riemann_client::Client, path: &str) -> Result<(), Failure> {
+    use std::io::{BufRead, BufReader};
+
+    let reader: Box<dyn BufRead + Send> = if path == "-" {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        let file = std::fs::File::open(path)
+            .map_err(|e| Failure::new(1, format!("Failed to read {}: {}", path, e)))?;
+        Box::new(BufReader::new(file))
+    };
+    let interval = std::time::Duration::from_secs(1);
+    let summary = riemann_client::input::send(reader, client, 100, interval, |line, e| {
+        eprintln!("{}:{}: {}", path, line, e)
+    })
+    .map_err(|e| Failure::from(e).context("Failed to send events"))?;
+    println!(
+        "--> {} events from {} ({} invalid lines)",
+        summary.events, path, summary.errors
+    );
+    Ok(())
+}
+
+fn main() {
     let args: Args = Docopt::new(USAGE)
         .and_then(|d| d.deserialize())
-        .unwrap_or_else(|e| e.exit());
+        .unwrap_or_else(|e| {
+            if e.fatal() {
+                eprintln!("{}", e);
+                exit(EXIT_USAGE)
+            }
+            e.exit()
+        });
+
+    if let Err(failure) = run(args) {
+        eprintln!("riemann-cli: {}", failure.message);
+        exit(failure.code)
+    }
+}
+
+fn run(mut args: Args) -> Result<(), Failure> {
+    use riemann_client::format;
+    use riemann_client::proto::Attribute;
 
     if args.flag_version {
         println!("riemann_cli v{}", env!("CARGO_PKG_VERSION"));
-        return;
+        return Ok(());
     }
 
-    if args.flag_mtls.is_some()
-        && (args.flag_cafile.is_none() || args.flag_cert.is_none() || args.flag_key.is_none())
+    args.profile = profile(&args).map_err(Failure::usage)?;
+    if args.profile.mtls == Some(true)
+        && (args.profile.cafile.is_none()
+            || args.profile.cert.is_none()
+            || args.profile.key.is_none())
     {
-        panic!("Args '--cafile', '--cert', '--key' are required using mTLS option.")
+        return Err(Failure::usage(
+            "Args '--cafile', '--cert', '--key' are required using mTLS option.",
+        ));
     }
 
-    let mut client = match args.flag_mtls {
-        Some(_) => riemann_client::Client::connect_tls(
-            &args.flag_server_host,
-            args.flag_server_port,
-            &args.flag_cafile.unwrap(),
-            &args.flag_cert.unwrap(),
-            &args.flag_key.unwrap(),
-        )
-        .unwrap(),
-        None => {
-            let addr: (&str, u16) = (&args.flag_server_host, args.flag_server_port);
-            riemann_client::Client::connect(&addr).unwrap()
-        }
-    };
+    if args.flag_interval == 0 {
+        return Err(Failure::usage("--interval must be at least 1 second"));
+    }
+    if args.flag_subscribe && args.profile.mtls == Some(true) {
+        return Err(Failure::usage(
+            "--subscribe can't be used with mTLS, as the websocket is not encrypted",
+        ));
+    }
+
+    let format: format::Format = args.flag_format.parse().map_err(Failure::usage)?;
+    let columns = args
+        .flag_columns
+        .as_deref()
+        .map(format::parse_columns)
+        .transpose()
+        .map_err(Failure::usage)?;
+
+    if args.cmd_health {
+        health(&args);
+    }
+
+    if args.cmd_watch {
+        watch(&args);
+    }
+
+    if args.cmd_repl {
+        return repl(&args);
+    }
+
+    if args.cmd_statsd {
+        let interval = std::time::Duration::from_secs(args.flag_interval);
+        let listen = args.flag_listen.as_deref().unwrap_or("0.0.0.0:8125");
+        let server = riemann_client::statsd::Server::bind(listen, interval)
+            .map_err(|e| Failure::new(1, format!("Failed to listen on {}: {}", listen, e)))?;
+        server
+            .run(|| connect(&args))
+            .map_err(|e| Failure::new(1, e))?;
+        return Ok(());
+    }
+
+    if args.cmd_graphite {
+        use riemann_client::client::{BatchClient, BatchOptions};
 
-    if args.cmd_send {
+        let listen = args.flag_listen.as_deref().unwrap_or("0.0.0.0:2003");
+        let mapping = args
+            .flag_mapping
+            .as_deref()
+            .unwrap_or("")
+            .parse()
+            .map_err(Failure::usage)?;
+        let server = riemann_client::graphite::Server::bind(listen, mapping)
+            .map_err(|e| Failure::new(1, format!("Failed to listen on {}: {}", listen, e)))?;
+        let connect_args = args.clone();
+        let client = BatchClient::new(move || connect(&connect_args), BatchOptions::default());
+        server.run(client).map_err(|e| Failure::new(1, e))?;
+        return Ok(());
+    }
+
+    if args.cmd_exec {
+        exec(&args);
+    }
+
+    let mut client = connect(&args).map_err(|e| {
+        Failure::from(e).context(format!(
+            "Failed to connect to {}:{}",
+            args.profile.host(),
+            args.profile.port()
+        ))
+    })?;
+
+    if args.cmd_send && args.flag_input.is_some() {
+        send_input(&mut client, args.flag_input.as_deref().unwrap())?;
+    } else if args.cmd_send {
         let mut event = riemann_client::proto::Event::new();
 
         if let Some(x) = args.flag_time {
@@ -107,9 +539,10 @@ fn main() {
         if let Some(x) = args.flag_state {
             event.set_state(x);
         }
-        if let Some(x) = args.flag_service {
-            event.set_service(x);
-        }
+        event.set_service(
+            args.flag_service
+                .unwrap_or_else(|| "riemann-cli".to_string()),
+        );
         if let Some(x) = args.flag_host {
             event.set_host(x);
         }
@@ -152,25 +585,46 @@ fn main() {
         }
 
         println!("--> {{ {:?} }}", event);
-        let response = client.event(event).unwrap();
-        println!("<-- {{ {:?} }}", response);
+        client
+            .event(event)
+            .map_err(|e| Failure::from(e).context("Failed to send event"))?;
+        println!("<-- {{ ok: true }}");
     } else if args.cmd_query {
-        let events = client.query(args.arg_query).unwrap();
-
-        println!(
-            "{:<10} {:<10} {:<55} {:<10} {:<10}",
-            "HOSTNAME", "TIME", "SERVICE", "METRIC", "STATE"
-        );
+        let events = client
+            .query(args.arg_query)
+            .map_err(|e| Failure::from(e).context("Query failed"))?;
+        format::write(
+            &mut std::io::stdout().lock(),
+            &events,
+            format,
+            columns.as_deref(),
+        )
+        .map_err(|e| Failure::new(1, e))?;
+    } else if args.cmd_scrape {
+        let timeout = Some(std::time::Duration::from_secs(10));
+        let events = riemann_client::prometheus::scrape(&args.arg_source, timeout)
+            .map_err(|e| Failure::new(1, format!("Failed to scrape {}: {}", args.arg_source, e)))?;
+        let count = events.len();
+        client
+            .events(events)
+            .map_err(|e| Failure::from(e).context("Failed to send events"))?;
+        println!("--> {} events from {}", count, args.arg_source);
+    } else if args.cmd_check {
+        let mut runner = riemann_client::check::CheckRunner::new(args.arg_command);
+        if let Some(x) = args.flag_service {
+            runner.set_service(x);
+        }
+        if let Some(x) = args.flag_ttl {
+            runner.set_ttl(x);
+        }
 
-        for event in events {
-            println!(
-                "{:<10} {:<10} {:<55} {:<10} {:<10}",
-                event.get_host(),
-                event.get_time(),
-                event.get_service(),
-                event.get_metric_f(),
-                event.get_state()
-            );
+        let events = runner.run();
+        for event in events.iter() {
+            println!("--> {{ {:?} }}", event);
         }
+        client
+            .events(events)
+            .map_err(|e| Failure::from(e).context("Failed to send events"))?;
     }
+    Ok(())
 }
@@ -0,0 +1,390 @@
+//! An OpenTelemetry metrics exporter that sends metrics to Riemann as events.
+//!
+//! Each data point becomes an event with the instrument name as the service,
+//! the resource's `host.name` as the host, and its attributes and the other
+//! resource attributes as event attributes, where the data point's attributes
+//! take precedence. Sums and gauges are sent with their value. Histograms are
+//! sent as events named like `service count`, `service sum`, `service min` and
+//! `service max`, and percentiles like `service p99` estimated from the bucket
+//! bounds.
+
+use std::future::{ready, Future};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ::opentelemetry::{Key, KeyValue};
+use ::opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use ::opentelemetry_sdk::metrics::data::{
+    AggregatedMetrics, HistogramDataPoint, Metric, MetricData, ResourceMetrics,
+};
+use ::opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use ::opentelemetry_sdk::metrics::Temporality;
+
+use super::client::Client;
+use super::proto::{Attribute, Event};
+use super::registry::{percentile_name, DEFAULT_PERCENTILES};
+use super::Result;
+
+/// A data point value, sent as `metric_d` for floats and `metric_sint64` for integers.
+trait Value: Copy {
+    fn set_metric(self, event: &mut Event);
+    fn as_f64(self) -> f64;
+}
+
+impl Value for f64 {
+    fn set_metric(self, event: &mut Event) {
+        event.set_metric_d(self);
+    }
+
+    fn as_f64(self) -> f64 {
+        self
+    }
+}
+
+impl Value for i64 {
+    fn set_metric(self, event: &mut Event) {
+        event.set_metric_sint64(self);
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+impl Value for u64 {
+    fn set_metric(self, event: &mut Event) {
+        event.set_metric_sint64(self as i64);
+    }
+
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+}
+
+/// The fields shared by all events for one instrument.
+struct Template<'a> {
+    host: Option<&'a str>,
+    resource: &'a [(String, String)],
+    metric: &'a Metric,
+}
+
+impl Template<'_> {
+    fn event<'k>(
+        &self,
+        service: String,
+        time: SystemTime,
+        attributes: impl Iterator<Item = &'k KeyValue>,
+    ) -> Event {
+        let mut event = Event::new();
+        let micros = time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_micros() as i64);
+        event.set_time(micros / 1_000_000);
+        event.set_time_micros(micros);
+        if let Some(host) = self.host {
+            event.set_host(host.to_string());
+        }
+        event.set_service(service);
+        if !self.metric.description().is_empty() {
+            event.set_description(self.metric.description().to_string());
+        }
+
+        let attribute = |key: String, value: String| {
+            let mut attribute = Attribute::new();
+            attribute.set_key(key);
+            attribute.set_value(value);
+            attribute
+        };
+        let mut attributes: Vec<Attribute> = attributes
+            .map(|kv| attribute(kv.key.as_str().to_string(), kv.value.as_str().into_owned()))
+            .collect();
+        for (key, value) in self.resource {
+            if !attributes.iter().any(|a| a.get_key() == key) {
+                attributes.push(attribute(key.clone(), value.clone()));
+            }
+        }
+        event.set_attributes(attributes.into());
+        event
+    }
+
+    fn events<T: Value>(&self, data: &MetricData<T>, events: &mut Vec<Event>) {
+        let name = self.metric.name();
+        match data {
+            MetricData::Gauge(gauge) => {
+                for point in gauge.data_points() {
+                    let mut event = self.event(name.to_string(), gauge.time(), point.attributes());
+                    point.value().set_metric(&mut event);
+                    events.push(event);
+                }
+            }
+            MetricData::Sum(sum) => {
+                for point in sum.data_points() {
+                    let mut event = self.event(name.to_string(), sum.time(), point.attributes());
+                    point.value().set_metric(&mut event);
+                    events.push(event);
+                }
+            }
+            MetricData::Histogram(histogram) => {
+                for point in histogram.data_points() {
+                    let event = |suffix: &str| {
+                        self.event(
+                            format!("{} {}", name, suffix),
+                            histogram.time(),
+                            point.attributes(),
+                        )
+                    };
+
+                    let mut count = event("count");
+                    count.set_metric_sint64(point.count() as i64);
+                    events.push(count);
+                    let mut sum = event("sum");
+                    point.sum().set_metric(&mut sum);
+                    events.push(sum);
+
+                    for (suffix, value) in [("min", point.min()), ("max", point.max())] {
+                        if let Some(value) = value {
+                            let mut e = event(suffix);
+                            value.set_metric(&mut e);
+                            events.push(e);
+                        }
+                    }
+
+                    for &p in DEFAULT_PERCENTILES {
+                        if let Some(value) = estimate_percentile(point, p) {
+                            let mut e = event(&percentile_name(p));
+                            e.set_metric_d(value);
+                            events.push(e);
+                        }
+                    }
+                }
+            }
+            MetricData::ExponentialHistogram(histogram) => {
+                for point in histogram.data_points() {
+                    let event = |suffix: &str| {
+                        self.event(
+                            format!("{} {}", name, suffix),
+                            histogram.time(),
+                            point.attributes(),
+                        )
+                    };
+
+                    let mut count = event("count");
+                    count.set_metric_sint64(point.count() as i64);
+                    events.push(count);
+                    let mut sum = event("sum");
+                    point.sum().set_metric(&mut sum);
+                    events.push(sum);
+                }
+            }
+        }
+    }
+}
+
+/// Estimate a percentile as the upper bound of the bucket containing it,
+/// limited to the maximum recorded value.
+fn estimate_percentile<T: Value>(point: &HistogramDataPoint<T>, percentile: f64) -> Option<f64> {
+    if point.count() == 0 {
+        return None;
+    }
+    let max = point.max().map(Value::as_f64);
+    let rank = (percentile * point.count() as f64).ceil().max(1.0) as u64;
+
+    let mut seen = 0;
+    let mut bounds = point.bounds();
+    for count in point.bucket_counts() {
+        seen += count;
+        let bound = bounds.next();
+        if seen >= rank {
+            return match (bound, max) {
+                (Some(bound), Some(max)) => Some(bound.min(max)),
+                (Some(bound), None) => Some(bound),
+                // The last bucket has no upper bound.
+                (None, max) => max,
+            };
+        }
+    }
+    max
+}
+
+/// Convert OpenTelemetry metrics to events.
+pub fn events(metrics: &ResourceMetrics) -> Vec<Event> {
+    let host_key = Key::from_static_str("host.name");
+    let host = metrics
+        .resource()
+        .get(&host_key)
+        .map(|value| value.as_str().into_owned());
+    let resource: Vec<(String, String)> = metrics
+        .resource()
+        .iter()
+        .filter(|(key, _)| **key != host_key)
+        .map(|(key, value)| (key.as_str().to_string(), value.as_str().into_owned()))
+        .collect();
+
+    let mut events = Vec::new();
+    for scope in metrics.scope_metrics() {
+        for metric in scope.metrics() {
+            let template = Template {
+                host: host.as_deref(),
+                resource: &resource,
+                metric,
+            };
+            match metric.data() {
+                AggregatedMetrics::F64(data) => template.events(data, &mut events),
+                AggregatedMetrics::I64(data) => template.events(data, &mut events),
+                AggregatedMetrics::U64(data) => template.events(data, &mut events),
+            }
+        }
+    }
+    events
+}
+
+struct Connection {
+    connect: Box<dyn FnMut() -> Result<Client> + Send>,
+    client: Option<Client>,
+}
+
+/// A `PushMetricExporter` sending cumulative metrics to Riemann.
+///
+/// The exporter connects using the function passed to `RiemannExporter::new`,
+/// and calls it again to reconnect after a failed export.
+pub struct RiemannExporter {
+    connection: Mutex<Connection>,
+}
+
+impl RiemannExporter {
+    pub fn new<F>(connect: F) -> Self
+    where
+        F: FnMut() -> Result<Client> + Send + 'static,
+    {
+        RiemannExporter {
+            connection: Mutex::new(Connection {
+                connect: Box::new(connect),
+                client: None,
+            }),
+        }
+    }
+
+    fn send(&self, events: Vec<Event>) -> OTelSdkResult {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        let connection = &mut *connection;
+        let client = match connection.client {
+            Some(ref mut client) => client,
+            None => connection.client.insert(
+                (connection.connect)()
+                    .map_err(|e| OTelSdkError::InternalFailure(format!("{}", e)))?,
+            ),
+        };
+
+        client.events(events).map_err(|e| {
+            connection.client = None;
+            OTelSdkError::InternalFailure(format!("{}", e))
+        })
+    }
+}
+
+impl PushMetricExporter for RiemannExporter {
+    fn export(&self, metrics: &ResourceMetrics) -> impl Future<Output = OTelSdkResult> + Send {
+        ready(self.send(events(metrics)))
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        Ok(())
+    }
+
+    fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+        let mut connection = self.connection.lock().unwrap_or_else(|e| e.into_inner());
+        connection.client = None;
+        Ok(())
+    }
+
+    fn temporality(&self) -> Temporality {
+        Temporality::Cumulative
+    }
+}
+
+impl ::std::fmt::Debug for RiemannExporter {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "RiemannExporter")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ::opentelemetry::metrics::MeterProvider;
+    use ::opentelemetry::KeyValue;
+    use ::opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+    use ::opentelemetry_sdk::Resource;
+
+    use super::super::client::test_server::TestServer;
+    use super::super::proto::Event;
+    use super::RiemannExporter;
+
+    fn find<'a>(events: &'a [Event], service: &str) -> &'a Event {
+        events
+            .iter()
+            .find(|e| e.get_service() == service)
+            .unwrap_or_else(|| panic!("No event for {}", service))
+    }
+
+    #[test]
+    fn export() {
+        let server = TestServer::start();
+        let reader = PeriodicReader::builder(RiemannExporter::new(server.connector())).build();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(
+                Resource::builder_empty()
+                    .with_attribute(KeyValue::new("host.name", "web01"))
+                    .with_attribute(KeyValue::new("service.name", "api"))
+                    .with_attribute(KeyValue::new("method", "ignored"))
+                    .build(),
+            )
+            .build();
+
+        let meter = provider.meter("test");
+        let requests = meter
+            .u64_counter("requests")
+            .with_description("Requests handled")
+            .build();
+        requests.add(3, &[KeyValue::new("method", "GET")]);
+        meter.f64_gauge("temperature").build().record(21.5, &[]);
+        let latency = meter
+            .f64_histogram("latency")
+            .with_boundaries(vec![0.1, 0.5, 1.0])
+            .build();
+        for value in [0.05, 0.2, 0.3, 0.7] {
+            latency.record(value, &[]);
+        }
+
+        provider.force_flush().unwrap();
+        let events = server.events();
+
+        let event = find(&events, "requests");
+        assert_eq!(event.get_host(), "web01");
+        assert_eq!(event.get_metric_sint64(), 3);
+        assert_eq!(event.get_description(), "Requests handled");
+        let mut attributes: Vec<(&str, &str)> = event
+            .get_attributes()
+            .iter()
+            .map(|a| (a.get_key(), a.get_value()))
+            .collect();
+        attributes.sort();
+        assert_eq!(attributes, vec![("method", "GET"), ("service.name", "api")]);
+        assert!(find(&events, "temperature")
+            .get_attributes()
+            .iter()
+            .any(|a| a.get_key() == "service.name" && a.get_value() == "api"));
+
+        assert_eq!(find(&events, "temperature").get_metric_d(), 21.5);
+        assert_eq!(find(&events, "latency count").get_metric_sint64(), 4);
+        assert_eq!(find(&events, "latency max").get_metric_d(), 0.7);
+        assert_eq!(find(&events, "latency p50").get_metric_d(), 0.5);
+        assert_eq!(find(&events, "latency p99").get_metric_d(), 0.7);
+
+        provider.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+//! Metrics for the current process, read from `/proc/self`.
+//!
+//! `report` starts a thread sending the process's resident memory, CPU
+//! time, open file descriptors, thread count and uptime on an interval,
+//! giving any service basic liveness telemetry.
+
+use std::fs;
+use std::time::Duration;
+
+use super::client::hostname::hostname;
+use super::client::Client;
+use super::proto::{Attribute, Event};
+use super::registry::Reporter;
+use super::utils::now_micros;
+use super::Result;
+
+/// Fields read from `/proc/self/stat`.
+#[derive(Debug, PartialEq)]
+struct Stat {
+    /// User and system CPU time, in clock ticks.
+    cpu_ticks: u64,
+    threads: u64,
+    /// The time the process started after boot, in clock ticks.
+    start_ticks: u64,
+}
+
+/// Parse `/proc/[pid]/stat`, whose second field is the command name in
+/// parentheses, which may itself contain spaces or parentheses.
+fn parse_stat(stat: &str) -> Option<Stat> {
+    let fields: Vec<&str> = stat
+        .get(stat.rfind(')')? + 1..)?
+        .split_whitespace()
+        .collect();
+    let field = |n: usize| -> Option<u64> { fields.get(n - 3)?.parse().ok() };
+    Some(Stat {
+        cpu_ticks: field(14)? + field(15)?,
+        threads: field(20)?,
+        start_ticks: field(22)?,
+    })
+}
+
+/// Parse the resident set size in bytes from `/proc/[pid]/status`.
+fn parse_rss(status: &str) -> Option<i64> {
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kilobytes: i64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kilobytes * 1024)
+}
+
+fn clock_ticks() -> f64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as f64,
+        _ => 100.0,
+    }
+}
+
+/// Read the current process's metrics as events.
+pub fn events() -> Result<Vec<Event>> {
+    let stat = fs::read_to_string("/proc/self/stat")?;
+    let stat = parse_stat(&stat).ok_or_else(|| invalid("/proc/self/stat"))?;
+    let rss = parse_rss(&fs::read_to_string("/proc/self/status")?)
+        .ok_or_else(|| invalid("/proc/self/status"))?;
+    let fds = fs::read_dir("/proc/self/fd")?.count() as i64;
+    let boot_uptime: f64 = fs::read_to_string("/proc/uptime")?
+        .split_whitespace()
+        .next()
+        .and_then(|f| f.parse().ok())
+        .ok_or_else(|| invalid("/proc/uptime"))?;
+
+    let ticks = clock_ticks();
+    let host = hostname()?;
+    let mut pid = Attribute::new();
+    pid.set_key("pid".to_string());
+    pid.set_value(::std::process::id().to_string());
+
+    let event = |service: &str| {
+        let mut event = Event::new();
+        event.set_time(now_micros() / 1_000_000);
+        event.set_host(host.clone());
+        event.set_service(format!("process {}", service));
+        event.set_state("ok".to_string());
+        event.set_attributes(vec![pid.clone()].into());
+        event
+    };
+
+    let mut rss_event = event("rss");
+    rss_event.set_metric_sint64(rss);
+    let mut cpu_event = event("cpu seconds");
+    cpu_event.set_metric_d(stat.cpu_ticks as f64 / ticks);
+    let mut fds_event = event("fds");
+    fds_event.set_metric_sint64(fds);
+    let mut threads_event = event("threads");
+    threads_event.set_metric_sint64(stat.threads as i64);
+    let mut uptime_event = event("uptime");
+    uptime_event.set_metric_d((boot_uptime - stat.start_ticks as f64 / ticks).max(0.0));
+
+    Ok(vec![
+        rss_event,
+        cpu_event,
+        fds_event,
+        threads_event,
+        uptime_event,
+    ])
+}
+
+fn invalid(path: &str) -> ::std::io::Error {
+    ::std::io::Error::new(
+        ::std::io::ErrorKind::InvalidData,
+        format!("Could not parse {}", path),
+    )
+}
+
+/// Start a thread sending the current process's metrics every `interval` to
+/// the client returned by `connect`. The thread stops when the returned
+/// `Reporter` is dropped.
+pub fn report<F>(connect: F, interval: Duration) -> Reporter
+where
+    F: FnMut() -> Result<Client> + Send + 'static,
+{
+    Reporter::start(|| events().unwrap_or_default(), connect, interval)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::client::hostname::hostname;
+    use super::{events, parse_rss, parse_stat, Stat};
+
+    #[test]
+    fn parse_proc_files() {
+        let stat = "1234 (my (odd) app) S 1 1234 1234 0 -1 4194560 500 0 0 0 \
+                    150 50 0 0 20 0 7 0 9000 100000 200 18446744073709551615";
+        assert_eq!(
+            parse_stat(stat),
+            Some(Stat {
+                cpu_ticks: 200,
+                threads: 7,
+                start_ticks: 9000
+            })
+        );
+
+        let status = "Name:\tapp\nVmPeak:\t 2000 kB\nVmRSS:\t    1500 kB\nThreads:\t7\n";
+        assert_eq!(parse_rss(status), Some(1500 * 1024));
+    }
+
+    #[test]
+    fn current_process() {
+        let events = events().unwrap();
+        let services: Vec<&str> = events.iter().map(|e| e.get_service()).collect();
+        assert_eq!(
+            services,
+            vec![
+                "process rss",
+                "process cpu seconds",
+                "process fds",
+                "process threads",
+                "process uptime"
+            ]
+        );
+        assert!(events.iter().all(|e| e.get_host() == hostname().unwrap()));
+        assert!(events[0].get_metric_sint64() > 0);
+        assert!(events[3].get_metric_sint64() >= 1);
+    }
+}
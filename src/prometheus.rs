@@ -0,0 +1,324 @@
+//! Convert metrics in the Prometheus text exposition format into events.
+//!
+//! Each sample becomes an event with the metric name as the service, the
+//! sample value as `metric_d` and the labels as attributes. The `# HELP` text
+//! for a metric is used as the description of its events.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::time::Duration;
+
+use super::http;
+use super::proto::{Attribute, Event};
+use super::utils::{now_micros, Error};
+use super::Result;
+
+/// A single sample from the exposition format.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+    /// Milliseconds since the unix epoch.
+    pub timestamp: Option<i64>,
+}
+
+/// Parse `name{label="value",...} value [timestamp]`.
+fn parse_sample(line: &str) -> Option<Sample> {
+    let name_end = line.find(['{', ' ', '\t']).unwrap_or(line.len());
+    let name = &line[..name_end];
+    let mut rest = &line[name_end..];
+
+    let mut labels = Vec::new();
+    if let Some(inner) = rest.strip_prefix('{') {
+        rest = inner;
+        loop {
+            rest = rest.trim_start_matches([' ', ',']);
+            if let Some(after) = rest.strip_prefix('}') {
+                rest = after;
+                break;
+            }
+            let (key, after) = rest.split_once('=')?;
+            let (value, after) = parse_label_value(after.trim_start().strip_prefix('"')?)?;
+            labels.push((key.trim().to_string(), value));
+            rest = after;
+        }
+    }
+
+    let mut fields = rest.split_whitespace();
+    let value = parse_value(fields.next()?)?;
+    let timestamp = match fields.next() {
+        Some(timestamp) => Some(timestamp.parse().ok()?),
+        None => None,
+    };
+
+    if name.is_empty() || fields.next().is_some() {
+        return None;
+    }
+
+    Some(Sample {
+        name: name.to_string(),
+        labels,
+        value,
+        timestamp,
+    })
+}
+
+/// Parse an escaped label value up to its closing quote, returning the rest of the line.
+fn parse_label_value(input: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = input.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((value, &input[index + 1..])),
+            '\\' => match chars.next()?.1 {
+                'n' => value.push('\n'),
+                other => value.push(other),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Unescape help text, where `\\` is a backslash and `\n` is a new line.
+fn unescape_help(text: &str) -> String {
+    let mut help = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.next_if_eq(&'n').is_some() => help.push('\n'),
+            '\\' if chars.next_if_eq(&'\\').is_some() => help.push('\\'),
+            c => help.push(c),
+        }
+    }
+    help
+}
+
+fn parse_value(value: &str) -> Option<f64> {
+    match value {
+        "+Inf" | "Inf" => Some(f64::INFINITY),
+        "-Inf" => Some(f64::NEG_INFINITY),
+        "NaN" => Some(f64::NAN),
+        value => value.parse().ok(),
+    }
+}
+
+/// Parse the exposition format, returning the samples and the help text for each metric.
+fn parse_text(text: &str) -> Result<(Vec<Sample>, HashMap<String, String>)> {
+    let mut samples = Vec::new();
+    let mut help = HashMap::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.trim_start().splitn(3, ' ');
+            if let (Some("HELP"), Some(name), Some(text)) =
+                (words.next(), words.next(), words.next())
+            {
+                help.insert(name.to_string(), unescape_help(text));
+            }
+            continue;
+        }
+        let sample = parse_sample(line).ok_or_else(|| {
+            Error::Parse(format!("Invalid sample on line {}: {}", number + 1, line))
+        })?;
+        samples.push(sample);
+    }
+
+    Ok((samples, help))
+}
+
+/// Parse samples from the exposition format.
+pub fn parse(text: &str) -> Result<Vec<Sample>> {
+    Ok(parse_text(text)?.0)
+}
+
+/// The help text for a sample, which for histograms, summaries and
+/// OpenMetrics counters is given for the name without the sample's suffix.
+fn help_for<'a>(help: &'a HashMap<String, String>, name: &str) -> Option<&'a String> {
+    help.get(name).or_else(|| {
+        ["_bucket", "_sum", "_count", "_total"]
+            .iter()
+            .find_map(|suffix| help.get(name.strip_suffix(suffix)?))
+    })
+}
+
+impl From<Sample> for Event {
+    fn from(sample: Sample) -> Self {
+        let mut event = Event::new();
+        let micros = match sample.timestamp {
+            Some(millis) => millis * 1000,
+            None => now_micros(),
+        };
+        event.set_time(micros / 1_000_000);
+        event.set_time_micros(micros);
+        event.set_service(sample.name);
+        event.set_metric_d(sample.value);
+        event.set_attributes(
+            sample
+                .labels
+                .into_iter()
+                .map(|(key, value)| {
+                    let mut attribute = Attribute::new();
+                    attribute.set_key(key);
+                    attribute.set_value(value);
+                    attribute
+                })
+                .collect(),
+        );
+        event
+    }
+}
+
+/// Convert metrics in the exposition format to events.
+pub fn events(text: &str) -> Result<Vec<Event>> {
+    let (samples, help) = parse_text(text)?;
+    Ok(samples
+        .into_iter()
+        .map(|sample| {
+            let description = help_for(&help, &sample.name).cloned();
+            let mut event = Event::from(sample);
+            if let Some(description) = description {
+                event.set_description(description);
+            }
+            event
+        })
+        .collect())
+}
+
+/// Read metrics from an `http://` URL or a local file and convert them to events.
+pub fn scrape(source: &str, timeout: Option<Duration>) -> Result<Vec<Event>> {
+    let text = match source.strip_prefix("http://") {
+        Some(url) => {
+            let (authority, path) = match url.find('/') {
+                Some(index) => (&url[..index], &url[index..]),
+                None => (url, "/metrics"),
+            };
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) => (
+                    host,
+                    port.parse()
+                        .map_err(|_| Error::Http(format!("Invalid port in {}", source)))?,
+                ),
+                None => (authority, 80),
+            };
+
+            let mut response = http::get(host, port, path, "text/plain", timeout)?;
+            let mut text = String::new();
+            response.body.read_to_string(&mut text)?;
+            text
+        }
+        None if source.contains("://") => {
+            return Err(Error::Http(format!("Unsupported URL: {}", source)))
+        }
+        None => fs::read_to_string(source)?,
+    };
+    events(&text)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::{events, parse, parse_sample, parse_text, scrape, Sample};
+
+    const TEXT: &str = r#"# HELP http_requests_total The total number of HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method="post",code="200"} 1027 1395066363000
+http_requests_total{method="post",code="400"}    3 1395066363000
+
+# A comment
+metric_without_labels 12.47
+# HELP latency Request latency.
+# TYPE latency histogram
+latency_bucket{le="0.5"} 129
+latency_bucket{le="+Inf"} 144
+latency_sum 53.4
+"#;
+
+    #[test]
+    fn parse_samples() {
+        assert_eq!(
+            parse_sample(
+                r#"msdos_file_access_time_seconds{path="C:\\DIR\\FILE.TXT",error="Cannot find file:\n\"FILE.TXT\""} 1.458255915e9"#
+            ),
+            Some(Sample {
+                name: "msdos_file_access_time_seconds".to_string(),
+                labels: vec![
+                    ("path".to_string(), "C:\\DIR\\FILE.TXT".to_string()),
+                    (
+                        "error".to_string(),
+                        "Cannot find file:\n\"FILE.TXT\"".to_string()
+                    ),
+                ],
+                value: 1.458255915e9,
+                timestamp: None,
+            })
+        );
+        assert_eq!(parse_sample("up{} -Inf").unwrap().value, f64::NEG_INFINITY);
+        assert!(parse_sample("up{job=\"a} 1").is_none());
+        assert!(parse_sample("up one").is_none());
+
+        assert_eq!(parse(TEXT).unwrap().len(), 6);
+        assert!(parse("up 1\nup").is_err());
+
+        let (_, help) = parse_text(r"# HELP up C:\\new\nline \d").unwrap();
+        assert_eq!(help["up"], "C:\\new\nline \\d");
+    }
+
+    #[test]
+    fn samples_to_events() {
+        let events = events(TEXT).unwrap();
+
+        assert_eq!(events[0].get_service(), "http_requests_total");
+        assert_eq!(events[0].get_metric_d(), 1027.0);
+        assert_eq!(events[0].get_time(), 1395066363);
+        assert_eq!(
+            events[0].get_description(),
+            "The total number of HTTP requests."
+        );
+        let attributes: Vec<(&str, &str)> = events[0]
+            .get_attributes()
+            .iter()
+            .map(|a| (a.get_key(), a.get_value()))
+            .collect();
+        assert_eq!(attributes, vec![("method", "post"), ("code", "200")]);
+
+        assert_eq!(events[2].get_service(), "metric_without_labels");
+        assert!(events[2].get_description().is_empty());
+        assert_eq!(events[3].get_description(), "Request latency.");
+        assert_eq!(events[5].get_service(), "latency_sum");
+        assert_eq!(events[5].get_description(), "Request latency.");
+    }
+
+    #[test]
+    fn scrape_http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let body = "up 1\n";
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+
+        let events = scrape(&format!("http://127.0.0.1:{}/metrics", port), None).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_service(), "up");
+        assert!(scrape("https://example.com/metrics", None).is_err());
+    }
+}
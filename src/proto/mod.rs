@@ -1,3 +1,76 @@
+use std::fmt;
+
 mod mod_pb;
 
-pub use self::mod_pb::*;
\ No newline at end of file
+pub use self::mod_pb::*;
+
+/// The metric of an event, from whichever of its metric fields is set.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    /// The `metric_sint64` field.
+    Int(i64),
+    /// The `metric_d` field.
+    Double(f64),
+    /// The `metric_f` field.
+    Float(f32),
+}
+
+impl Metric {
+    /// The metric as a double, which may lose precision for large integers.
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Metric::Int(metric) => metric as f64,
+            Metric::Double(metric) => metric,
+            Metric::Float(metric) => f64::from(metric),
+        }
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Metric::Int(metric) => metric.fmt(f),
+            Metric::Double(metric) => metric.fmt(f),
+            Metric::Float(metric) => metric.fmt(f),
+        }
+    }
+}
+
+impl Event {
+    /// Returns the metric of this event, preferring `metric_sint64`, then
+    /// `metric_d` and then `metric_f` if more than one is set.
+    pub fn metric(&self) -> Option<Metric> {
+        if self.has_metric_sint64() {
+            Some(Metric::Int(self.get_metric_sint64()))
+        } else if self.has_metric_d() {
+            Some(Metric::Double(self.get_metric_d()))
+        } else if self.has_metric_f() {
+            Some(Metric::Float(self.get_metric_f()))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Event, Metric};
+
+    #[test]
+    fn metric() {
+        let mut event = Event::new();
+        assert_eq!(event.metric(), None);
+
+        event.set_metric_f(0.1);
+        assert_eq!(event.metric(), Some(Metric::Float(0.1)));
+        assert_eq!(event.metric().unwrap().to_string(), "0.1");
+
+        event.set_metric_d(11.0);
+        assert_eq!(event.metric(), Some(Metric::Double(11.0)));
+        assert_eq!(event.metric().unwrap().to_string(), "11");
+
+        event.set_metric_sint64(-3);
+        assert_eq!(event.metric(), Some(Metric::Int(-3)));
+        assert_eq!(event.metric().unwrap().as_f64(), -3.0);
+    }
+}
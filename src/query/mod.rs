@@ -0,0 +1,379 @@
+//! Parsing and local evaluation of Riemann query strings.
+//!
+//! Queries are parsed into an `Expr` that can be checked before it is sent to
+//! a server, or tested against events client-side with `Expr::matches`.
+
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use regex::Regex;
+
+use super::proto::{Event, Metric};
+
+pub use self::parser::{parse, ParseError};
+
+mod parser;
+
+/// A parsed Riemann query.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    True,
+    False,
+    Nil,
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Tagged(String),
+    Compare(Field, Operator, Value),
+    Match(Field, Operator, Pattern),
+}
+
+/// An event field that can appear on the left hand side of a comparison.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Field {
+    Host,
+    Service,
+    State,
+    Description,
+    Metric,
+    MetricF,
+    Ttl,
+    Time,
+    Attribute(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    /// `=~`, a match where `%` is a wildcard.
+    Like,
+    /// `~=`, a regular expression search.
+    Regex,
+}
+
+/// A literal value on the right hand side of a comparison.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Nil,
+}
+
+/// A compiled pattern from a `=~` or `~=` comparison.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    pub source: String,
+    regex: Regex,
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl<'a> From<&'a str> for Field {
+    fn from(name: &'a str) -> Self {
+        match name {
+            "host" => Field::Host,
+            "service" => Field::Service,
+            "state" => Field::State,
+            "description" => Field::Description,
+            "metric" => Field::Metric,
+            "metric_f" => Field::MetricF,
+            "ttl" => Field::Ttl,
+            "time" => Field::Time,
+            name => Field::Attribute(name.to_string()),
+        }
+    }
+}
+
+impl Field {
+    /// Returns the value of this field on an event, or `Value::Nil` if it is not set.
+    pub fn get(&self, event: &Event) -> Value {
+        fn string(has: bool, value: &str) -> Value {
+            if has {
+                Value::String(value.to_string())
+            } else {
+                Value::Nil
+            }
+        }
+
+        match *self {
+            Field::Host => string(event.has_host(), event.get_host()),
+            Field::Service => string(event.has_service(), event.get_service()),
+            Field::State => string(event.has_state(), event.get_state()),
+            Field::Description => string(event.has_description(), event.get_description()),
+            Field::Metric => match event.metric() {
+                Some(Metric::Int(metric)) => Value::Int(metric),
+                Some(metric) => Value::Float(metric.as_f64()),
+                None => Value::Nil,
+            },
+            Field::MetricF if event.has_metric_f() => Value::Float(f64::from(event.get_metric_f())),
+            Field::Ttl if event.has_ttl() => Value::Float(f64::from(event.get_ttl())),
+            Field::Time if event.has_time() => Value::Int(event.get_time()),
+            Field::Time if event.has_time_micros() => {
+                Value::Int(event.get_time_micros() / 1_000_000)
+            }
+            Field::Attribute(ref key) => event
+                .get_attributes()
+                .iter()
+                .find(|attribute| attribute.get_key() == key)
+                .map_or(Value::Nil, |attribute| {
+                    Value::String(attribute.get_value().to_string())
+                }),
+            _ => Value::Nil,
+        }
+    }
+}
+
+impl Value {
+    fn as_f64(&self) -> Option<f64> {
+        match *self {
+            Value::Int(int) => Some(int as f64),
+            Value::Float(float) => Some(float),
+            _ => None,
+        }
+    }
+
+    fn equals(&self, other: &Value) -> bool {
+        match (self.as_f64(), other.as_f64()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self == other,
+        }
+    }
+}
+
+impl Expr {
+    /// Test if an event matches this query, using the same semantics as the Riemann index.
+    pub fn matches(&self, event: &Event) -> bool {
+        match *self {
+            Expr::True => true,
+            Expr::False | Expr::Nil => false,
+            Expr::And(ref a, ref b) => a.matches(event) && b.matches(event),
+            Expr::Or(ref a, ref b) => a.matches(event) || b.matches(event),
+            Expr::Not(ref a) => !a.matches(event),
+            Expr::Tagged(ref tag) => event.get_tags().iter().any(|t| t == tag),
+            Expr::Compare(ref field, operator, ref value) => {
+                let actual = field.get(event);
+                match operator {
+                    Operator::Equal => actual.equals(value),
+                    Operator::NotEqual => !actual.equals(value),
+                    _ => match (actual.as_f64(), value.as_f64()) {
+                        (Some(a), Some(b)) => match operator {
+                            Operator::Less => a < b,
+                            Operator::LessEqual => a <= b,
+                            Operator::Greater => a > b,
+                            Operator::GreaterEqual => a >= b,
+                            _ => unreachable!("Operator handled by Expr::Match"),
+                        },
+                        _ => false,
+                    },
+                }
+            }
+            Expr::Match(ref field, _, ref pattern) => match field.get(event) {
+                Value::String(ref string) => pattern.regex.is_match(string),
+                _ => false,
+            },
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        match *self {
+            Expr::True => write!(f, "true"),
+            Expr::False => write!(f, "false"),
+            Expr::Nil => write!(f, "nil"),
+            Expr::And(ref a, ref b) => write!(f, "({} and {})", a, b),
+            Expr::Or(ref a, ref b) => write!(f, "({} or {})", a, b),
+            Expr::Not(ref a) => write!(f, "not {}", a),
+            Expr::Tagged(ref tag) => write!(f, "tagged {}", Value::String(tag.clone())),
+            Expr::Compare(ref field, operator, ref value) => {
+                write!(f, "{} {} {}", field, operator, value)
+            }
+            Expr::Match(ref field, operator, ref pattern) => write!(
+                f,
+                "{} {} {}",
+                field,
+                operator,
+                Value::String(pattern.source.clone())
+            ),
+        }
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        let name = match *self {
+            Field::Host => "host",
+            Field::Service => "service",
+            Field::State => "state",
+            Field::Description => "description",
+            Field::Metric => "metric",
+            Field::MetricF => "metric_f",
+            Field::Ttl => "ttl",
+            Field::Time => "time",
+            Field::Attribute(ref name) => name,
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Display for Operator {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        let symbol = match *self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "!=",
+            Operator::Less => "<",
+            Operator::LessEqual => "<=",
+            Operator::Greater => ">",
+            Operator::GreaterEqual => ">=",
+            Operator::Like => "=~",
+            Operator::Regex => "~=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        match *self {
+            Value::String(ref string) => {
+                write!(f, "\"")?;
+                for c in string.chars() {
+                    match c {
+                        '"' => write!(f, "\\\"")?,
+                        '\\' => write!(f, "\\\\")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Value::Int(int) => write!(f, "{}", int),
+            Value::Float(float) => write!(f, "{:?}", float),
+            Value::Bool(boolean) => write!(f, "{}", boolean),
+            Value::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::proto::{Attribute, Event};
+    use super::{parse, Expr, Field, Operator, Value};
+
+    fn event() -> Event {
+        let mut event = Event::new();
+        event.set_host("www1".to_string());
+        event.set_service("api req/s".to_string());
+        event.set_state("ok".to_string());
+        event.set_metric_sint64(42);
+        event.set_tags(vec!["web".to_string(), "prod".to_string()].into());
+        event.set_attributes(
+            vec![{
+                let mut attribute = Attribute::new();
+                attribute.set_key("env".to_string());
+                attribute.set_value("production".to_string());
+                attribute
+            }]
+            .into(),
+        );
+        event
+    }
+
+    fn matches(query: &str) -> bool {
+        parse(query).unwrap().matches(&event())
+    }
+
+    #[test]
+    fn parse_precedence() {
+        assert_eq!(
+            parse("true or false and not nil").unwrap(),
+            Expr::Or(
+                Box::new(Expr::True),
+                Box::new(Expr::And(
+                    Box::new(Expr::False),
+                    Box::new(Expr::Not(Box::new(Expr::Nil)))
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn parse_compare() {
+        assert_eq!(
+            parse("metric >= -1.5e3").unwrap(),
+            Expr::Compare(Field::Metric, Operator::GreaterEqual, Value::Float(-1500.0))
+        );
+        assert_eq!(
+            parse("(env == \"a \\\"b\\\"\")").unwrap(),
+            Expr::Compare(
+                Field::Attribute("env".to_string()),
+                Operator::Equal,
+                Value::String("a \"b\"".to_string())
+            )
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = parse("service = ").unwrap_err();
+        assert_eq!(error.position, 10);
+        assert_eq!(error.message, "expected value, found end of query");
+
+        let error = parse("(true or false").unwrap_err();
+        assert_eq!(error.position, 14);
+
+        let error = parse("host = \"a\" host").unwrap_err();
+        assert_eq!(error.position, 11);
+
+        let error = parse("service ~= \"(\"").unwrap_err();
+        assert_eq!(error.position, 11);
+
+        let error = parse("service = \"abc").unwrap_err();
+        assert_eq!(error.message, "unterminated string");
+    }
+
+    #[test]
+    fn display_round_trip() {
+        let query = "(not service =~ \"api%\" or (metric > 3 and tagged \"web\"))";
+        assert_eq!(parse(query).unwrap().to_string(), query);
+    }
+
+    #[test]
+    fn evaluate() {
+        assert!(matches("true"));
+        assert!(!matches("nil"));
+        assert!(matches("host = \"www1\" and state != \"critical\""));
+        assert!(matches("service =~ \"api%\""));
+        assert!(!matches("service =~ \"req%\""));
+        assert!(matches("service ~= \"req\""));
+        assert!(matches("metric = 42.0 and metric > 41 and metric <= 42"));
+        assert!(!matches("metric_f > 0"));
+        assert!(matches("metric_f = nil and description = nil"));
+        assert!(matches("tagged \"prod\" and not tagged \"dev\""));
+        assert!(matches("env = \"production\""));
+        assert!(!matches("env > 1"));
+        assert!(matches("missing = nil"));
+    }
+}
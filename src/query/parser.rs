@@ -0,0 +1,377 @@
+//! Recursive descent parser for the Riemann query language.
+//!
+//! Follows the grammar in Riemann's `query.g4`:
+//!
+//! ```text
+//! or      : and ('or' and)*
+//! and     : (not | primary) ('and' (not | primary))*
+//! not     : 'not' (not | primary)
+//! primary : '(' or ')' | simple
+//! simple  : 'true' | 'false' | 'nil' | 'null' | 'tagged' String | field operator value
+//! ```
+
+use std::fmt::{Display, Formatter};
+
+use regex::Regex;
+
+use super::{Expr, Field, Operator, Pattern, Value};
+
+/// An error encountered while parsing a query, with the byte offset it occurred at.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl ParseError {
+    fn new<S: Into<String>>(position: usize, message: S) -> Self {
+        ParseError {
+            position,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl ::std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    Word(String),
+    String(String),
+    Int(i64),
+    Float(f64),
+    Operator(Operator),
+    End,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter) -> ::std::fmt::Result {
+        match *self {
+            Token::Open => write!(f, "'('"),
+            Token::Close => write!(f, "')'"),
+            Token::Word(ref word) => write!(f, "'{}'", word),
+            Token::String(ref string) => write!(f, "{:?}", string),
+            Token::Int(int) => write!(f, "{}", int),
+            Token::Float(float) => write!(f, "{}", float),
+            Token::Operator(operator) => write!(f, "'{}'", operator),
+            Token::End => write!(f, "end of query"),
+        }
+    }
+}
+
+/// Splits a query string into tokens paired with their starting byte offset.
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let token = match c {
+            '(' => {
+                chars.next();
+                Token::Open
+            }
+            ')' => {
+                chars.next();
+                Token::Close
+            }
+            '"' => {
+                chars.next();
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((position, '\\')) => match chars.next() {
+                            Some((_, '"')) => string.push('"'),
+                            Some((_, '\\')) => string.push('\\'),
+                            Some((_, '/')) => string.push('/'),
+                            Some((_, 'b')) => string.push('\u{8}'),
+                            Some((_, 'f')) => string.push('\u{c}'),
+                            Some((_, 'n')) => string.push('\n'),
+                            Some((_, 'r')) => string.push('\r'),
+                            Some((_, 't')) => string.push('\t'),
+                            Some((_, 'u')) => {
+                                let digits: String = (0..4)
+                                    .filter_map(|_| chars.next())
+                                    .map(|(_, c)| c)
+                                    .collect();
+                                match u32::from_str_radix(&digits, 16)
+                                    .ok()
+                                    .and_then(::std::char::from_u32)
+                                {
+                                    Some(c) if digits.len() == 4 => string.push(c),
+                                    _ => {
+                                        return Err(ParseError::new(
+                                            position,
+                                            "invalid unicode escape",
+                                        ))
+                                    }
+                                }
+                            }
+                            _ => return Err(ParseError::new(position, "invalid escape sequence")),
+                        },
+                        Some((_, c)) => string.push(c),
+                        None => return Err(ParseError::new(start, "unterminated string")),
+                    }
+                }
+                Token::String(string)
+            }
+            '=' | '!' | '<' | '>' | '~' => {
+                chars.next();
+                let next = chars.peek().map(|&(_, c)| c);
+                let (operator, consumed) = match (c, next) {
+                    ('=', Some('~')) => (Operator::Like, true),
+                    ('=', Some('=')) => (Operator::Equal, true),
+                    ('=', _) => (Operator::Equal, false),
+                    ('~', Some('=')) => (Operator::Regex, true),
+                    ('!', Some('=')) => (Operator::NotEqual, true),
+                    ('<', Some('=')) => (Operator::LessEqual, true),
+                    ('<', _) => (Operator::Less, false),
+                    ('>', Some('=')) => (Operator::GreaterEqual, true),
+                    ('>', _) => (Operator::Greater, false),
+                    _ => return Err(ParseError::new(start, format!("unexpected '{}'", c))),
+                };
+                if consumed {
+                    chars.next();
+                }
+                Token::Operator(operator)
+            }
+            '-' | '0'..='9' => {
+                let mut end = start;
+                let mut is_float = false;
+                while let Some(&(position, c)) = chars.peek() {
+                    let accept = match c {
+                        '0'..='9' => true,
+                        '-' => position == start,
+                        '.' | 'e' | 'E' => {
+                            is_float = true;
+                            true
+                        }
+                        '+' => matches!(input[..position].chars().last(), Some('e') | Some('E')),
+                        _ => false,
+                    };
+                    if !accept {
+                        break;
+                    }
+                    end = position + c.len_utf8();
+                    chars.next();
+                }
+                let text = &input[start..end];
+                let number = if is_float {
+                    text.parse().map(Token::Float).ok()
+                } else {
+                    text.parse().map(Token::Int).ok()
+                };
+                number
+                    .ok_or_else(|| ParseError::new(start, format!("invalid number '{}'", text)))?
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut word = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '-' || c == '.' {
+                        word.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Word(word)
+            }
+            _ => return Err(ParseError::new(start, format!("unexpected '{}'", c))),
+        };
+
+        tokens.push((start, token));
+    }
+
+    tokens.push((input.len(), Token::End));
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.index].1
+    }
+
+    fn position(&self) -> usize {
+        self.tokens[self.index].0
+    }
+
+    fn next(&mut self) -> (usize, Token) {
+        let token = self.tokens[self.index].clone();
+        if self.index < self.tokens.len() - 1 {
+            self.index += 1;
+        }
+        token
+    }
+
+    fn peek_word(&self, word: &str) -> bool {
+        matches!(*self.peek(), Token::Word(ref w) if w == word)
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        ParseError::new(
+            self.position(),
+            format!("expected {}, found {}", expected, self.peek()),
+        )
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.and()?;
+        while self.peek_word("or") {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.not()?;
+        while self.peek_word("and") {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, ParseError> {
+        if self.peek_word("not") {
+            self.next();
+            Ok(Expr::Not(Box::new(self.not()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        if *self.peek() == Token::Open {
+            self.next();
+            let expr = self.or()?;
+            return match self.peek() {
+                Token::Close => {
+                    self.next();
+                    Ok(expr)
+                }
+                _ => Err(self.unexpected("')'")),
+            };
+        }
+
+        let (position, token) = self.next();
+        let word = match token {
+            Token::Word(word) => word,
+            token => {
+                return Err(ParseError::new(
+                    position,
+                    format!("expected expression, found {}", token),
+                ))
+            }
+        };
+
+        match word.as_str() {
+            "true" => return Ok(Expr::True),
+            "false" => return Ok(Expr::False),
+            "nil" | "null" => return Ok(Expr::Nil),
+            "tagged" => {
+                return match self.next() {
+                    (_, Token::String(tag)) => Ok(Expr::Tagged(tag)),
+                    (position, token) => Err(ParseError::new(
+                        position,
+                        format!("expected tag string, found {}", token),
+                    )),
+                };
+            }
+            "and" | "or" | "not" => {
+                return Err(ParseError::new(
+                    position,
+                    format!("expected expression, found '{}'", word),
+                ))
+            }
+            _ => {}
+        }
+
+        let field = Field::from(word.as_str());
+        let operator = match self.next() {
+            (_, Token::Operator(operator)) => operator,
+            (position, token) => {
+                return Err(ParseError::new(
+                    position,
+                    format!("expected operator, found {}", token),
+                ))
+            }
+        };
+
+        let (position, token) = self.next();
+        let value = match token {
+            Token::String(string) => Value::String(string),
+            Token::Int(int) => Value::Int(int),
+            Token::Float(float) => Value::Float(float),
+            Token::Word(ref word) if word == "true" => Value::Bool(true),
+            Token::Word(ref word) if word == "false" => Value::Bool(false),
+            Token::Word(ref word) if word == "nil" || word == "null" => Value::Nil,
+            token => {
+                return Err(ParseError::new(
+                    position,
+                    format!("expected value, found {}", token),
+                ))
+            }
+        };
+
+        match operator {
+            Operator::Like | Operator::Regex => {
+                let source = match value {
+                    Value::String(string) => string,
+                    value => {
+                        return Err(ParseError::new(
+                            position,
+                            format!("expected pattern string, found {}", value),
+                        ))
+                    }
+                };
+                let regex = if operator == Operator::Like {
+                    like_to_regex(&source)
+                } else {
+                    Regex::new(&source)
+                };
+                let regex = regex
+                    .map_err(|e| ParseError::new(position, format!("invalid pattern: {}", e)))?;
+                Ok(Expr::Match(field, operator, Pattern { source, regex }))
+            }
+            _ => Ok(Expr::Compare(field, operator, value)),
+        }
+    }
+}
+
+/// Compiles a `=~` pattern, where `%` matches any sequence of characters.
+fn like_to_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    let parts: Vec<String> = pattern.split('%').map(regex::escape).collect();
+    Regex::new(&format!("(?s)^{}$", parts.join(".*")))
+}
+
+/// Parse a Riemann query string into an expression tree.
+pub fn parse(input: &str) -> Result<Expr, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        index: 0,
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        Token::End => Ok(expr),
+        _ => Err(parser.unexpected("'and', 'or' or end of query")),
+    }
+}
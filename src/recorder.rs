@@ -0,0 +1,289 @@
+//! A `metrics` recorder that aggregates metrics locally and sends them to Riemann.
+//!
+//! Counters and gauges are sent with their current value, using the metric
+//! name as the service and the labels as attributes. Histograms are sent as
+//! one event per percentile, named like `service p99`, computed from the
+//! values recorded since the previous flush.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ::metrics::{
+    Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SetRecorderError,
+    SharedString, Unit,
+};
+
+use super::client::Client;
+use super::proto::{Attribute, Event};
+use super::registry::{self, percentile_events, Reporter};
+use super::utils::now_micros;
+use super::Result;
+
+pub use super::registry::{percentile_name, DEFAULT_PERCENTILES};
+
+/// A background thread flushing metrics on an interval, stopped when dropped.
+pub type Flusher = Reporter;
+
+impl HistogramFn for registry::Histogram {
+    fn record(&self, value: f64) {
+        registry::Histogram::record(self, value);
+    }
+}
+
+#[derive(Default)]
+struct State {
+    counters: Mutex<HashMap<Key, Arc<AtomicU64>>>,
+    gauges: Mutex<HashMap<Key, Arc<AtomicU64>>>,
+    histograms: Mutex<HashMap<Key, registry::Histogram>>,
+    descriptions: Mutex<HashMap<String, String>>,
+    percentiles: Vec<f64>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> ::std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn register<T: Clone + Default>(map: &Mutex<HashMap<Key, T>>, key: &Key) -> T {
+    lock(map).entry(key.clone()).or_default().clone()
+}
+
+impl State {
+    fn event(&self, key: &Key, service: String) -> Event {
+        let mut event = Event::new();
+        event.set_time(now_micros() / 1_000_000);
+        if let Some(description) = lock(&self.descriptions).get(key.name()) {
+            event.set_description(description.clone());
+        }
+        event.set_service(service);
+        event.set_attributes(
+            key.labels()
+                .map(|label| {
+                    let mut attribute = Attribute::new();
+                    attribute.set_key(label.key().to_string());
+                    attribute.set_value(label.value().to_string());
+                    attribute
+                })
+                .collect(),
+        );
+        event
+    }
+
+    fn events(&self) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for (key, counter) in lock(&self.counters).iter() {
+            let mut event = self.event(key, key.name().to_string());
+            event.set_metric_sint64(counter.load(Ordering::Relaxed) as i64);
+            events.push(event);
+        }
+
+        for (key, gauge) in lock(&self.gauges).iter() {
+            let mut event = self.event(key, key.name().to_string());
+            event.set_metric_d(f64::from_bits(gauge.load(Ordering::Relaxed)));
+            events.push(event);
+        }
+
+        for (key, histogram) in lock(&self.histograms).iter() {
+            let values = histogram.take_sorted();
+            events.extend(percentile_events(&values, &self.percentiles, |p| {
+                self.event(key, format!("{} {}", key.name(), p))
+            }));
+        }
+
+        events
+    }
+
+    fn describe(&self, key: KeyName, description: SharedString) {
+        lock(&self.descriptions).insert(key.as_str().to_string(), description.into_owned());
+    }
+}
+
+/// A `metrics::Recorder` that keeps metrics in memory until they are flushed to Riemann.
+pub struct RiemannRecorder {
+    state: Arc<State>,
+}
+
+/// A handle to the metrics of a `RiemannRecorder`, which remains usable after
+/// the recorder is installed.
+#[derive(Clone)]
+pub struct RecorderHandle {
+    state: Arc<State>,
+}
+
+impl Default for RiemannRecorder {
+    fn default() -> Self {
+        RiemannRecorder::new()
+    }
+}
+
+impl RiemannRecorder {
+    /// Create a recorder sending the default percentiles for histograms.
+    pub fn new() -> Self {
+        RiemannRecorder::with_percentiles(DEFAULT_PERCENTILES.to_vec())
+    }
+
+    /// Create a recorder sending the given percentiles (between 0 and 1) for histograms.
+    pub fn with_percentiles(percentiles: Vec<f64>) -> Self {
+        RiemannRecorder {
+            state: Arc::new(State {
+                percentiles,
+                ..State::default()
+            }),
+        }
+    }
+
+    /// Get a handle used to flush this recorder's metrics.
+    pub fn handle(&self) -> RecorderHandle {
+        RecorderHandle {
+            state: self.state.clone(),
+        }
+    }
+
+    /// Install this recorder as the global recorder, returning a handle to it.
+    pub fn install(self) -> ::std::result::Result<RecorderHandle, SetRecorderError<Self>> {
+        let handle = self.handle();
+        ::metrics::set_global_recorder(self)?;
+        Ok(handle)
+    }
+}
+
+impl Recorder for RiemannRecorder {
+    fn describe_counter(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.state.describe(key, description);
+    }
+
+    fn describe_gauge(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.state.describe(key, description);
+    }
+
+    fn describe_histogram(&self, key: KeyName, _unit: Option<Unit>, description: SharedString) {
+        self.state.describe(key, description);
+    }
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        Counter::from_arc(register(&self.state.counters, key))
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        Gauge::from_arc(register(&self.state.gauges, key))
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        Histogram::from_arc(Arc::new(register(&self.state.histograms, key)))
+    }
+}
+
+impl RecorderHandle {
+    /// Build events for the current metrics, clearing the values recorded by histograms.
+    pub fn events(&self) -> Vec<Event> {
+        self.state.events()
+    }
+
+    /// Send the current metrics to Riemann.
+    pub fn flush(&self, client: &mut Client) -> Result<()> {
+        let events = self.events();
+        if !events.is_empty() {
+            client.events(events)?;
+        }
+        Ok(())
+    }
+
+    /// Start a background thread flushing metrics every `interval` to the
+    /// client returned by `connect`, which is called again after a failed flush.
+    pub fn flush_every<F>(&self, connect: F, interval: Duration) -> Flusher
+    where
+        F: FnMut() -> Result<Client> + Send + 'static,
+    {
+        let handle = self.clone();
+        Reporter::start(move || handle.events(), connect, interval)
+    }
+}
+
+impl ::std::fmt::Debug for RiemannRecorder {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "RiemannRecorder {{ percentiles: {:?} }}",
+            self.state.percentiles
+        )
+    }
+}
+
+impl ::std::fmt::Debug for RecorderHandle {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "RecorderHandle {{ percentiles: {:?} }}",
+            self.state.percentiles
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use ::metrics::{counter, describe_counter, gauge, histogram, with_local_recorder};
+
+    use super::super::client::test_server::TestServer;
+    use super::super::proto::Event;
+    use super::RiemannRecorder;
+
+    fn find<'a>(events: &'a [Event], service: &str) -> &'a Event {
+        events
+            .iter()
+            .find(|e| e.get_service() == service)
+            .unwrap_or_else(|| panic!("No event for {}", service))
+    }
+
+    #[test]
+    fn aggregate() {
+        let recorder = RiemannRecorder::new();
+        let handle = recorder.handle();
+
+        with_local_recorder(&recorder, || {
+            describe_counter!("requests", "Requests handled");
+            counter!("requests", "method" => "GET").increment(2);
+            counter!("requests", "method" => "GET").increment(3);
+            gauge!("connections").set(4.0);
+            for i in 1..=100 {
+                histogram!("latency").record(i as f64);
+            }
+        });
+
+        let events = handle.events();
+        assert_eq!(events.len(), 5);
+
+        let requests = find(&events, "requests");
+        assert_eq!(requests.get_metric_sint64(), 5);
+        assert_eq!(requests.get_description(), "Requests handled");
+        assert_eq!(requests.get_attributes()[0].get_key(), "method");
+        assert_eq!(requests.get_attributes()[0].get_value(), "GET");
+
+        assert_eq!(find(&events, "connections").get_metric_d(), 4.0);
+        assert_eq!(find(&events, "latency p50").get_metric_d(), 50.0);
+        assert_eq!(find(&events, "latency p95").get_metric_d(), 95.0);
+        assert_eq!(find(&events, "latency p99").get_metric_d(), 99.0);
+
+        // Histogram values are cleared by each flush, counters and gauges are not.
+        assert_eq!(handle.events().len(), 2);
+    }
+
+    #[test]
+    fn flush_every() {
+        let server = TestServer::start();
+        let recorder = RiemannRecorder::with_percentiles(vec![0.5]);
+        let handle = recorder.handle();
+        let flusher = handle.flush_every(server.connector(), Duration::from_secs(60));
+
+        with_local_recorder(&recorder, || histogram!("latency").record(1.5));
+        drop(flusher);
+
+        let events = server.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_service(), "latency p50");
+        assert_eq!(events[0].get_metric_d(), 1.5);
+    }
+}
@@ -0,0 +1,637 @@
+//! Metrics aggregated in memory and reported to Riemann as batches of events.
+//!
+//! A `Registry` hands out counters, gauges, meters, timers and histograms by
+//! name. Updating a metric is a cheap atomic operation (or a short lock on
+//! one of several shards for histograms and timers), and the aggregated
+//! values are only turned into events when the registry is reported.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::client::Client;
+use super::proto::Event;
+use super::utils::now_micros;
+use super::Result;
+
+/// The percentiles reported for histograms and timers unless others are given.
+pub const DEFAULT_PERCENTILES: &[f64] = &[0.5, 0.95, 0.99];
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A count that only changes when it is incremented, reported as its total.
+#[derive(Clone, Debug, Default)]
+pub struct Counter(Arc<AtomicI64>);
+
+impl Counter {
+    pub fn increment(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, value: i64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    pub fn value(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that is set directly, reported as its latest value.
+#[derive(Clone, Debug, Default)]
+pub struct Gauge(Arc<AtomicU64>);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn value(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// A count of occurrences, reported as the rate per second since the last report.
+#[derive(Clone, Debug)]
+pub struct Meter(Arc<MeterState>);
+
+#[derive(Debug)]
+struct MeterState {
+    count: AtomicU64,
+    since: Mutex<Instant>,
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Meter(Arc::new(MeterState {
+            count: AtomicU64::new(0),
+            since: Mutex::new(Instant::now()),
+        }))
+    }
+}
+
+impl Meter {
+    pub fn mark(&self) {
+        self.mark_n(1);
+    }
+
+    pub fn mark_n(&self, count: u64) {
+        self.0.count.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// The rate since the last call, resetting the count.
+    fn take_rate(&self) -> f64 {
+        let mut since = lock(&self.0.since);
+        let count = self.0.count.swap(0, Ordering::Relaxed);
+        let elapsed = since.elapsed().as_secs_f64();
+        *since = Instant::now();
+        if elapsed > 0.0 {
+            count as f64 / elapsed
+        } else {
+            0.0
+        }
+    }
+}
+
+/// The number of shards a histogram's values are split between, so that
+/// threads recording values don't all wait on the same lock.
+const SHARDS: usize = 8;
+
+/// The most values each shard of a histogram keeps between reports.
+pub const RESERVOIR_SIZE: usize = 1028;
+
+/// The shard used by the current thread.
+fn shard() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    thread_local!(static SHARD: usize = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS);
+    SHARD.with(|shard| *shard)
+}
+
+/// A uniform sample of the values recorded since the last report.
+#[derive(Debug)]
+struct Reservoir {
+    values: Vec<f64>,
+    count: u64,
+    random: u64,
+}
+
+impl Default for Reservoir {
+    fn default() -> Self {
+        Reservoir {
+            values: Vec::new(),
+            count: 0,
+            random: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+}
+
+impl Reservoir {
+    fn record(&mut self, value: f64) {
+        self.count += 1;
+        if self.values.len() < RESERVOIR_SIZE {
+            self.values.push(value);
+            return;
+        }
+
+        // Keep each value with an equal chance by replacing a random value.
+        let index = (self.random() % self.count) as usize;
+        if index < RESERVOIR_SIZE {
+            self.values[index] = value;
+        }
+    }
+
+    fn random(&mut self) -> u64 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        self.random
+    }
+
+    /// Take `n` of the values, chosen at random, leaving the reservoir empty.
+    fn take(&mut self, n: usize) -> Vec<f64> {
+        let len = self.values.len();
+        for i in 0..n.min(len) {
+            let j = i + (self.random() % (len - i) as u64) as usize;
+            self.values.swap(i, j);
+        }
+        self.values.truncate(n);
+        self.count = 0;
+        ::std::mem::take(&mut self.values)
+    }
+}
+
+/// A distribution of values, reported as percentiles of the values recorded
+/// since the last report.
+///
+/// Each of the histogram's shards keeps at most `RESERVOIR_SIZE` values,
+/// chosen at random once more values than that are recorded, so the
+/// percentiles of busy histograms are estimates.
+#[derive(Clone, Debug)]
+pub struct Histogram(Arc<[Mutex<Reservoir>]>);
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram((0..SHARDS).map(|_| Mutex::default()).collect())
+    }
+}
+
+impl Histogram {
+    pub fn record(&self, value: f64) {
+        lock(&self.0[shard()]).record(value);
+    }
+
+    /// Take the recorded values, sorted, leaving the histogram empty.
+    ///
+    /// Shards that recorded more values than they kept are sampled less often,
+    /// so each shard contributes values in proportion to the number it recorded.
+    pub(crate) fn take_sorted(&self) -> Vec<f64> {
+        let mut reservoirs: Vec<_> = self.0.iter().map(|r| lock(r)).collect();
+        let total = reservoirs.iter().map(|r| r.count).sum::<u64>() as f64;
+        // The most values that can be taken while keeping the proportions,
+        // limited by the shard that kept the smallest share of its values.
+        let size = reservoirs
+            .iter()
+            .filter(|r| r.count > 0)
+            .map(|r| r.values.len() as f64 * total / r.count as f64)
+            .fold(total, f64::min);
+
+        let mut values = Vec::new();
+        for reservoir in &mut reservoirs {
+            let n = if reservoir.count > 0 {
+                (reservoir.count as f64 * size / total).round() as usize
+            } else {
+                0
+            };
+            values.extend(reservoir.take(n));
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        values
+    }
+}
+
+/// A histogram of durations in seconds.
+#[derive(Clone, Debug, Default)]
+pub struct Timer(Histogram);
+
+/// Records the time since it was created to a `Timer` when dropped.
+#[derive(Debug)]
+pub struct TimerGuard {
+    timer: Timer,
+    start: Instant,
+}
+
+impl Timer {
+    pub fn record(&self, duration: Duration) {
+        self.0.record(duration.as_secs_f64());
+    }
+
+    /// Start timing, recording the duration when the returned guard is dropped.
+    pub fn start(&self) -> TimerGuard {
+        TimerGuard {
+            timer: self.clone(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Time a function call.
+    pub fn time<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        let _guard = self.start();
+        f()
+    }
+}
+
+impl Drop for TimerGuard {
+    fn drop(&mut self) {
+        self.timer.record(self.start.elapsed());
+    }
+}
+
+/// The suffix used for a percentile's service, e.g. `p99` for 0.99 and `p999` for 0.999.
+pub fn percentile_name(percentile: f64) -> String {
+    if percentile >= 1.0 {
+        return "p100".to_string();
+    }
+    let digits = format!("{}", percentile);
+    let digits = digits.trim_start_matches("0.");
+    if digits.len() == 1 {
+        format!("p{}0", digits)
+    } else {
+        format!("p{}", digits)
+    }
+}
+
+/// The value at `percentile` (between 0 and 1) of sorted `values`, by nearest rank.
+fn percentile(values: &[f64], percentile: f64) -> f64 {
+    let rank = (percentile * values.len() as f64).ceil() as usize;
+    values[rank.clamp(1, values.len()) - 1]
+}
+
+/// Build an event for each percentile of sorted `values`, using `event` to
+/// create an event for a service.
+pub(crate) fn percentile_events<F>(values: &[f64], percentiles: &[f64], mut event: F) -> Vec<Event>
+where
+    F: FnMut(String) -> Event,
+{
+    if values.is_empty() {
+        return Vec::new();
+    }
+    percentiles
+        .iter()
+        .map(|&p| {
+            let mut e = event(percentile_name(p));
+            e.set_metric_d(percentile(values, p));
+            e
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct State {
+    counters: Mutex<HashMap<String, Counter>>,
+    gauges: Mutex<HashMap<String, Gauge>>,
+    meters: Mutex<HashMap<String, Meter>>,
+    histograms: Mutex<HashMap<String, Histogram>>,
+    timers: Mutex<HashMap<String, Timer>>,
+    percentiles: Vec<f64>,
+}
+
+fn get<T: Clone + Default>(map: &Mutex<HashMap<String, T>>, name: &str) -> T {
+    lock(map).entry(name.to_string()).or_default().clone()
+}
+
+fn event(service: String) -> Event {
+    let mut event = Event::new();
+    event.set_time(now_micros() / 1_000_000);
+    event.set_service(service);
+    event
+}
+
+/// A set of named metrics, which can be cloned and shared between threads.
+#[derive(Clone)]
+pub struct Registry {
+    state: Arc<State>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+impl Registry {
+    /// Create a registry reporting the default percentiles for histograms and timers.
+    pub fn new() -> Self {
+        Registry::with_percentiles(DEFAULT_PERCENTILES.to_vec())
+    }
+
+    /// Create a registry reporting the given percentiles (between 0 and 1)
+    /// for histograms and timers.
+    pub fn with_percentiles(percentiles: Vec<f64>) -> Self {
+        Registry {
+            state: Arc::new(State {
+                percentiles,
+                ..State::default()
+            }),
+        }
+    }
+
+    /// Get the counter with a name, creating it if it doesn't exist.
+    pub fn counter(&self, name: &str) -> Counter {
+        get(&self.state.counters, name)
+    }
+
+    /// Get the gauge with a name, creating it if it doesn't exist.
+    pub fn gauge(&self, name: &str) -> Gauge {
+        get(&self.state.gauges, name)
+    }
+
+    /// Get the meter with a name, creating it if it doesn't exist.
+    pub fn meter(&self, name: &str) -> Meter {
+        get(&self.state.meters, name)
+    }
+
+    /// Get the histogram with a name, creating it if it doesn't exist.
+    pub fn histogram(&self, name: &str) -> Histogram {
+        get(&self.state.histograms, name)
+    }
+
+    /// Get the timer with a name, creating it if it doesn't exist.
+    pub fn timer(&self, name: &str) -> Timer {
+        get(&self.state.timers, name)
+    }
+
+    /// Build events for the current metrics, resetting meters, histograms and timers.
+    pub fn events(&self) -> Vec<Event> {
+        let state = &self.state;
+        let mut events = Vec::new();
+
+        for (name, counter) in lock(&state.counters).iter() {
+            let mut e = event(name.clone());
+            e.set_metric_sint64(counter.value());
+            events.push(e);
+        }
+
+        for (name, gauge) in lock(&state.gauges).iter() {
+            let mut e = event(name.clone());
+            e.set_metric_d(gauge.value());
+            events.push(e);
+        }
+
+        for (name, meter) in lock(&state.meters).iter() {
+            let mut e = event(name.clone());
+            e.set_metric_d(meter.take_rate());
+            events.push(e);
+        }
+
+        let histograms = lock(&state.histograms);
+        let timers = lock(&state.timers);
+        let distributions = histograms
+            .iter()
+            .chain(timers.iter().map(|(name, timer)| (name, &timer.0)));
+        for (name, histogram) in distributions {
+            let values = histogram.take_sorted();
+            events.extend(percentile_events(&values, &state.percentiles, |p| {
+                event(format!("{} {}", name, p))
+            }));
+        }
+
+        events
+    }
+
+    /// Send the current metrics to Riemann.
+    pub fn report(&self, client: &mut Client) -> Result<()> {
+        let events = self.events();
+        if !events.is_empty() {
+            client.events(events)?;
+        }
+        Ok(())
+    }
+
+    /// Start a background thread reporting metrics every `interval` to the
+    /// client returned by `connect`.
+    pub fn report_every<F>(&self, connect: F, interval: Duration) -> Reporter
+    where
+        F: FnMut() -> Result<Client> + Send + 'static,
+    {
+        let registry = self.clone();
+        Reporter::start(move || registry.events(), connect, interval)
+    }
+}
+
+impl ::std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "Registry {{ percentiles: {:?} }}",
+            self.state.percentiles
+        )
+    }
+}
+
+/// A background thread sending events on an interval, stopped when dropped.
+///
+/// The thread connects using the `connect` function, and calls it again to
+/// reconnect after a failed send. Events that can't be sent are dropped.
+pub struct Reporter {
+    stop: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Reporter {
+    /// Start a thread sending the events returned by `events` every `interval`.
+    pub fn start<E, F>(mut events: E, mut connect: F, interval: Duration) -> Self
+    where
+        E: FnMut() -> Vec<Event> + Send + 'static,
+        F: FnMut() -> Result<Client> + Send + 'static,
+    {
+        let (stop, stopped) = channel();
+
+        let worker = thread::spawn(move || {
+            let mut client: Option<Client> = None;
+            loop {
+                let last = match stopped.recv_timeout(interval) {
+                    Err(RecvTimeoutError::Timeout) => false,
+                    Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+                };
+
+                let batch = events();
+                if !batch.is_empty() {
+                    if client.is_none() {
+                        client = connect().ok();
+                    }
+                    let sent = match client {
+                        Some(ref mut client) => client.events(batch).is_ok(),
+                        None => false,
+                    };
+                    if !sent {
+                        client = None;
+                    }
+                }
+
+                if last {
+                    return;
+                }
+            }
+        });
+
+        Reporter {
+            stop: Some(stop),
+            worker: Some(worker),
+        }
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        // Stopping the thread sends the events one last time.
+        self.stop.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl ::std::fmt::Debug for Reporter {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "Reporter")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::super::client::test_server::TestServer;
+    use super::super::proto::Event;
+    use super::{percentile_name, Histogram, Registry, RESERVOIR_SIZE};
+
+    fn metric(events: &[Event], service: &str) -> f64 {
+        let event = events
+            .iter()
+            .find(|e| e.get_service() == service)
+            .unwrap_or_else(|| panic!("No event for {}", service));
+        event.metric().unwrap().as_f64()
+    }
+
+    #[test]
+    fn percentile_names() {
+        assert_eq!(percentile_name(0.5), "p50");
+        assert_eq!(percentile_name(0.99), "p99");
+        assert_eq!(percentile_name(0.999), "p999");
+        assert_eq!(percentile_name(1.0), "p100");
+    }
+
+    #[test]
+    fn aggregate() {
+        let registry = Registry::with_percentiles(vec![0.5, 0.9]);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let registry = registry.clone();
+                thread::spawn(move || {
+                    for i in 1..=25 {
+                        registry.counter("requests").increment();
+                        registry.histogram("size").record(i as f64);
+                        registry.meter("hits").mark();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        registry.gauge("queue").set(3.5);
+        registry.timer("query").record(Duration::from_millis(250));
+
+        let events = registry.events();
+        assert_eq!(events.len(), 7);
+        assert_eq!(metric(&events, "requests"), 100.0);
+        assert_eq!(metric(&events, "queue"), 3.5);
+        assert!(metric(&events, "hits") > 0.0);
+        assert_eq!(metric(&events, "size p50"), 13.0);
+        assert_eq!(metric(&events, "size p90"), 23.0);
+        assert_eq!(metric(&events, "query p50"), 0.25);
+        assert_eq!(metric(&events, "query p90"), 0.25);
+
+        // Histograms and timers with no new values are not reported.
+        let events = registry.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(metric(&events, "requests"), 100.0);
+        assert_eq!(metric(&events, "hits"), 0.0);
+    }
+
+    #[test]
+    fn reservoir() {
+        let histogram = Histogram::default();
+        for i in 0..100_000 {
+            histogram.record(i as f64);
+        }
+        let values = histogram.take_sorted();
+        assert_eq!(values.len(), RESERVOIR_SIZE);
+        let median = values[values.len() / 2];
+        assert!((40_000.0..60_000.0).contains(&median), "{}", median);
+        assert!(histogram.take_sorted().is_empty());
+    }
+
+    #[test]
+    fn reservoir_threads() {
+        // One busy thread and one quiet thread with much larger values, which
+        // would make up half of the values if every shard counted the same.
+        let histogram = Histogram::default();
+        let busy = histogram.clone();
+        let quiet = histogram.clone();
+        thread::spawn(move || {
+            for i in 0..100_000 {
+                busy.record((i % 1000) as f64);
+            }
+        })
+        .join()
+        .unwrap();
+        thread::spawn(move || {
+            for _ in 0..500 {
+                quiet.record(1e6);
+            }
+        })
+        .join()
+        .unwrap();
+
+        let values = histogram.take_sorted();
+        let percentile = |p| super::percentile(&values, p);
+        assert!(
+            (400.0..600.0).contains(&percentile(0.5)),
+            "{}",
+            percentile(0.5)
+        );
+        assert!(
+            (900.0..1000.0).contains(&percentile(0.95)),
+            "{}",
+            percentile(0.95)
+        );
+        assert!(percentile(0.99) < 1000.0, "{}", percentile(0.99));
+        assert!(histogram.take_sorted().is_empty());
+    }
+
+    #[test]
+    fn report_every() {
+        let server = TestServer::start();
+        let registry = Registry::new();
+        let reporter = registry.report_every(server.connector(), Duration::from_secs(60));
+
+        registry.timer("work").time(|| ());
+        drop(reporter);
+
+        let services: Vec<String> = server
+            .events()
+            .iter()
+            .map(|e| e.get_service().to_string())
+            .collect();
+        assert_eq!(services.len(), 3);
+        assert!(services.contains(&"work p99".to_string()));
+    }
+}
@@ -0,0 +1,219 @@
+//! An interactive session that runs queries over one connection.
+//!
+//! Each line is either a query or a meta-command starting with `:`:
+//!
+//! * `:format <format>` sets the output format (see `format::Format`),
+//! * `:columns <list>` sets the output columns, or resets them if empty,
+//! * `:sort <order>` sets the order of results (see `client::Order`),
+//! * `:send <key=value>...` sends an event, in the format read by `input`,
+//! * `:help` lists the meta-commands and `:quit` ends the session.
+
+use std::io::Write;
+
+use super::client::{Client, Order};
+use super::format::{self, Column, Format};
+use super::input;
+use super::proto::Event;
+use super::utils::Error;
+use super::Result;
+
+/// A parsed line of input.
+#[derive(Debug)]
+pub enum Command {
+    Query(String),
+    Format(Format),
+    Columns(Option<Vec<Column>>),
+    Sort(Order),
+    Send(Box<Event>),
+    Help,
+    Quit,
+    Empty,
+}
+
+pub const HELP: &str = "\
+Enter a query, such as 'service = \"cpu\"', or a meta-command:
+    :format <format>        Output format: table, json, jsonl, csv or tsv.
+    :columns [<list>]       Comma separated columns to output, or the defaults if empty.
+    :sort <order>           Sort results by none, service, host, time or metric.
+    :send <key=value>...    Send an event, e.g. ':send service=x metric=1'.
+    :help                   Show this message.
+    :quit                   End the session.
+";
+
+impl Command {
+    /// Parse a line of input.
+    pub fn parse(line: &str) -> Result<Self> {
+        let line = line.trim();
+        let meta = match line.strip_prefix(':') {
+            Some(meta) => meta,
+            None if line.is_empty() => return Ok(Command::Empty),
+            None => return Ok(Command::Query(line.to_string())),
+        };
+
+        let (name, argument) = match meta.split_once(char::is_whitespace) {
+            Some((name, argument)) => (name, argument.trim()),
+            None => (meta, ""),
+        };
+        match name {
+            "format" => Ok(Command::Format(argument.parse()?)),
+            "columns" if argument.is_empty() => Ok(Command::Columns(None)),
+            "columns" => Ok(Command::Columns(Some(format::parse_columns(argument)?))),
+            "sort" => Ok(Command::Sort(argument.parse()?)),
+            "send" => match input::parse_line(argument)? {
+                Some(event) => Ok(Command::Send(Box::new(event))),
+                None => Err(Error::Parse("Expected an event to send".to_string())),
+            },
+            "help" | "h" => Ok(Command::Help),
+            "quit" | "q" | "exit" => Ok(Command::Quit),
+            _ => Err(Error::Parse(format!(
+                "Unknown command :{}, enter :help for a list of commands",
+                name
+            ))),
+        }
+    }
+}
+
+/// The connection and output settings for an interactive session.
+pub struct Session<F> {
+    connect: F,
+    client: Option<Client>,
+    format: Format,
+    columns: Option<Vec<Column>>,
+    order: Order,
+}
+
+impl<F: FnMut() -> Result<Client>> Session<F> {
+    /// Create a session that connects using `connect` when it first needs to,
+    /// and again after a request fails.
+    pub fn new(connect: F) -> Self {
+        Session {
+            connect,
+            client: None,
+            format: Format::Table,
+            columns: None,
+            order: Order::default(),
+        }
+    }
+
+    fn client(&mut self) -> Result<&mut Client> {
+        match self.client {
+            Some(ref mut client) => Ok(client),
+            None => Ok(self.client.insert((self.connect)()?)),
+        }
+    }
+
+    /// Send a request, dropping the connection if it is broken. Errors from
+    /// the server, such as for an invalid query, keep the connection.
+    fn request<T>(&mut self, f: impl FnOnce(&mut Client) -> Result<T>) -> Result<T> {
+        let result = f(self.client()?);
+        if let Err(Error::Io(_)) | Err(Error::Protobuf(_)) | Err(Error::TLS(_)) = result {
+            self.client = None;
+        }
+        result
+    }
+
+    /// Run a line of input, writing any output to `out`. Returns false if
+    /// the session should end.
+    pub fn run<W: Write>(&mut self, line: &str, out: &mut W) -> Result<bool> {
+        match Command::parse(line)? {
+            Command::Query(query) => {
+                let order = std::mem::take(&mut self.order);
+                let events = self.request(|client| client.query_ordered(query, &order));
+                self.order = order;
+                format::write(out, &events?, self.format, self.columns.as_deref())?;
+            }
+            Command::Format(format) => self.format = format,
+            Command::Columns(columns) => self.columns = columns,
+            Command::Sort(order) => self.order = order,
+            Command::Send(event) => {
+                self.request(|client| client.event(*event))?;
+                writeln!(out, "ok")?;
+            }
+            Command::Help => write!(out, "{}", HELP)?,
+            Command::Quit => return Ok(false),
+            Command::Empty => {}
+        }
+        Ok(true)
+    }
+}
+
+impl<F> ::std::fmt::Debug for Session<F> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("Session")
+            .field("connected", &self.client.is_some())
+            .field("format", &self.format)
+            .field("columns", &self.columns)
+            .field("order", &self.order)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::client::test_server::TestServer;
+    use super::super::client::Order;
+    use super::super::format::Format;
+    use super::{Command, Session};
+
+    #[test]
+    fn parse() {
+        assert!(matches!(
+            Command::parse("service = \"cpu\"").unwrap(),
+            Command::Query(ref q) if q == "service = \"cpu\""
+        ));
+        assert!(matches!(
+            Command::parse(":format jsonl").unwrap(),
+            Command::Format(Format::Jsonl)
+        ));
+        assert!(matches!(
+            Command::parse(":sort host").unwrap(),
+            Command::Sort(Order::HostService)
+        ));
+        assert!(matches!(
+            Command::parse(":columns").unwrap(),
+            Command::Columns(None)
+        ));
+        assert!(matches!(
+            Command::parse(":send service=x metric=1").unwrap(),
+            Command::Send(ref e) if e.get_service() == "x" && e.get_metric_sint64() == 1
+        ));
+        assert!(matches!(Command::parse("  ").unwrap(), Command::Empty));
+        assert!(matches!(Command::parse(":q").unwrap(), Command::Quit));
+        assert!(Command::parse(":format yaml").is_err());
+        assert!(Command::parse(":send").is_err());
+        assert!(Command::parse(":frobnicate").is_err());
+    }
+
+    #[test]
+    fn session() {
+        let server = TestServer::start();
+        let mut connects = 0;
+        let mut connector = server.connector();
+        let mut session = Session::new(|| {
+            connects += 1;
+            connector()
+        });
+        let mut out = Vec::new();
+
+        assert!(session.run(":columns service,metric", &mut out).unwrap());
+        assert!(session.run(":format csv", &mut out).unwrap());
+        assert!(session.run("true", &mut out).unwrap());
+        assert_eq!(server.message().get_query().get_string(), "true");
+        assert!(session.run(":send service=x", &mut out).unwrap());
+        assert_eq!(server.events()[0].get_service(), "x");
+
+        // A server error, such as for an invalid query, keeps the connection.
+        assert!(session.run("error", &mut out).is_err());
+        assert_eq!(server.message().get_query().get_string(), "error");
+        assert!(session.run("true", &mut out).unwrap());
+        assert_eq!(server.message().get_query().get_string(), "true");
+        assert!(!session.run(":quit", &mut out).unwrap());
+
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "service,metric\nok\nservice,metric\n"
+        );
+        drop(session);
+        assert_eq!(connects, 1);
+    }
+}
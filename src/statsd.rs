@@ -0,0 +1,336 @@
+//! A StatsD server that aggregates metrics and forwards them to Riemann.
+//!
+//! Metrics received over UDP are aggregated for each flush interval and then
+//! sent as events, with the metric name as the service:
+//!
+//! * counters (`c`) are sent as the total for the interval,
+//! * gauges (`g`) are sent as their latest value, on every flush,
+//! * timers (`ms` or `h`) are sent as percentiles, named like `service p99`,
+//!   and a count named `service count`, scaled by the sample rate like counters,
+//! * sets (`s`) are sent as the number of unique values in the interval.
+
+use std::collections::{HashMap, HashSet};
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use super::client::Client;
+use super::proto::Event;
+use super::registry::{percentile_events, DEFAULT_PERCENTILES};
+use super::utils::now_micros;
+use super::Result;
+
+/// The kind of a StatsD metric.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kind {
+    Counter,
+    Gauge,
+    Timer,
+    Set,
+}
+
+/// A single metric parsed from a StatsD packet.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub value: String,
+    pub kind: Kind,
+    pub sample_rate: f64,
+}
+
+/// Parse a line in the format `name:value|type[|@sample_rate]`.
+///
+/// Other sections, such as DogStatsD tags, are ignored.
+pub fn parse_line(line: &str) -> Option<Metric> {
+    let (name, rest) = line.trim().split_once(':')?;
+    let mut sections = rest.split('|');
+    let value = sections.next()?;
+    let kind = match sections.next()? {
+        "c" => Kind::Counter,
+        "g" => Kind::Gauge,
+        "ms" | "h" => Kind::Timer,
+        "s" => Kind::Set,
+        _ => return None,
+    };
+
+    let mut sample_rate = 1.0;
+    for section in sections {
+        if let Some(rate) = section.strip_prefix('@') {
+            sample_rate = rate.parse().ok().filter(|r| *r > 0.0)?;
+        }
+    }
+
+    if name.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some(Metric {
+        name: name.to_string(),
+        value: value.to_string(),
+        kind,
+        sample_rate,
+    })
+}
+
+/// The values of a timer in an interval, and how many values they represent.
+#[derive(Debug, Default)]
+struct Timer {
+    values: Vec<f64>,
+    count: f64,
+}
+
+/// Metrics aggregated over a flush interval.
+#[derive(Debug)]
+pub struct Aggregator {
+    counters: HashMap<String, f64>,
+    gauges: HashMap<String, f64>,
+    timers: HashMap<String, Timer>,
+    sets: HashMap<String, HashSet<String>>,
+    percentiles: Vec<f64>,
+}
+
+impl Default for Aggregator {
+    fn default() -> Self {
+        Aggregator::new(DEFAULT_PERCENTILES.to_vec())
+    }
+}
+
+impl Aggregator {
+    /// Create an aggregator sending the given percentiles (between 0 and 1) for timers.
+    pub fn new(percentiles: Vec<f64>) -> Self {
+        Aggregator {
+            counters: HashMap::new(),
+            gauges: HashMap::new(),
+            timers: HashMap::new(),
+            sets: HashMap::new(),
+            percentiles,
+        }
+    }
+
+    /// Add a metric, returning `false` if its value is invalid.
+    pub fn add(&mut self, metric: Metric) -> bool {
+        if metric.kind == Kind::Set {
+            self.sets
+                .entry(metric.name)
+                .or_default()
+                .insert(metric.value);
+            return true;
+        }
+
+        let value: f64 = match metric.value.parse() {
+            Ok(value) => value,
+            Err(_) => return false,
+        };
+        match metric.kind {
+            Kind::Counter => {
+                *self.counters.entry(metric.name).or_default() += value / metric.sample_rate;
+            }
+            Kind::Gauge => {
+                // A signed value changes the gauge instead of setting it.
+                let gauge = self.gauges.entry(metric.name).or_default();
+                if metric.value.starts_with(['+', '-']) {
+                    *gauge += value;
+                } else {
+                    *gauge = value;
+                }
+            }
+            Kind::Timer => {
+                let timer = self.timers.entry(metric.name).or_default();
+                timer.values.push(value);
+                timer.count += 1.0 / metric.sample_rate;
+            }
+            Kind::Set => unreachable!(),
+        }
+        true
+    }
+
+    /// Parse and add each metric in a packet, skipping invalid lines.
+    pub fn add_packet(&mut self, packet: &str) {
+        for metric in packet.lines().filter_map(parse_line) {
+            self.add(metric);
+        }
+    }
+
+    /// Build events for the metrics in this interval and start a new interval.
+    pub fn flush(&mut self) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for (name, count) in self.counters.drain() {
+            events.push(event(name, count));
+        }
+
+        for (name, value) in self.gauges.iter() {
+            events.push(event(name.clone(), *value));
+        }
+
+        for (name, mut timer) in self.timers.drain() {
+            timer.values.sort_by(|a, b| a.total_cmp(b));
+            events.extend(percentile_events(&timer.values, &self.percentiles, |p| {
+                event(format!("{} {}", name, p), 0.0)
+            }));
+            events.push(event(format!("{} count", name), timer.count));
+        }
+
+        for (name, values) in self.sets.drain() {
+            events.push(event(name, values.len() as f64));
+        }
+
+        events
+    }
+}
+
+fn event(service: String, metric: f64) -> Event {
+    let mut event = Event::new();
+    event.set_time(now_micros() / 1_000_000);
+    event.set_service(service);
+    event.set_metric_d(metric);
+    event
+}
+
+/// Receives StatsD metrics over UDP and forwards them to Riemann on an interval.
+#[derive(Debug)]
+pub struct Server {
+    socket: UdpSocket,
+    interval: Duration,
+    aggregator: Aggregator,
+}
+
+impl Server {
+    /// Listen for metrics on a UDP address, sending them every `interval`.
+    pub fn bind<A: ToSocketAddrs>(addr: A, interval: Duration) -> Result<Self> {
+        Ok(Server {
+            socket: UdpSocket::bind(addr)?,
+            interval,
+            aggregator: Aggregator::default(),
+        })
+    }
+
+    /// Set the percentiles sent for timers.
+    pub fn set_percentiles(&mut self, percentiles: Vec<f64>) {
+        self.aggregator.percentiles = percentiles;
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Receive and forward metrics to the client returned by `connect`,
+    /// which is called again after a failed send, until the socket fails.
+    pub fn run<F: FnMut() -> Result<Client>>(mut self, mut connect: F) -> Result<()> {
+        let mut client: Option<Client> = None;
+        let mut buffer = [0u8; 65535];
+        let mut deadline = Instant::now() + self.interval;
+
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if !timeout.is_zero() {
+                self.socket.set_read_timeout(Some(timeout))?;
+                match self.socket.recv_from(&mut buffer) {
+                    Ok((size, _)) => {
+                        self.aggregator
+                            .add_packet(&String::from_utf8_lossy(&buffer[..size]));
+                        continue;
+                    }
+                    // Read timeouts are reported as either kind, depending on the platform.
+                    Err(ref e)
+                        if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            deadline = Instant::now() + self.interval;
+            let events = self.aggregator.flush();
+            if events.is_empty() {
+                continue;
+            }
+            if client.is_none() {
+                client = connect().ok();
+            }
+            let sent = match client {
+                Some(ref mut client) => client.events(events).is_ok(),
+                None => false,
+            };
+            if !sent {
+                client = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::UdpSocket;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::super::client::test_server::TestServer;
+    use super::{parse_line, Aggregator, Kind, Metric, Server};
+
+    fn metrics(aggregator: &mut Aggregator) -> Vec<(String, f64)> {
+        let mut metrics: Vec<(String, f64)> = aggregator
+            .flush()
+            .iter()
+            .map(|e| (e.get_service().to_string(), e.get_metric_d()))
+            .collect();
+        metrics.sort_by(|a, b| a.0.cmp(&b.0));
+        metrics
+    }
+
+    #[test]
+    fn parse_lines() {
+        assert_eq!(
+            parse_line("requests:2|c|@0.5|#env:prod"),
+            Some(Metric {
+                name: "requests".to_string(),
+                value: "2".to_string(),
+                kind: Kind::Counter,
+                sample_rate: 0.5,
+            })
+        );
+        assert_eq!(parse_line("latency:320|ms").unwrap().kind, Kind::Timer);
+        assert!(parse_line("requests:2|x").is_none());
+        assert!(parse_line("requests|c").is_none());
+        assert!(parse_line("requests:1|c|@0").is_none());
+    }
+
+    #[test]
+    fn aggregate() {
+        let mut aggregator = Aggregator::new(vec![0.5, 0.9]);
+        aggregator.add_packet(
+            "requests:1|c\nrequests:2|c|@0.5\nqueue:10|g\nqueue:-3|g\n\
+             latency:10|ms\nlatency:20|ms|@0.5\nlatency:30|ms\nusers:a|s\nusers:b|s\nusers:a|s\n\
+             bad:x|c",
+        );
+
+        assert_eq!(
+            metrics(&mut aggregator),
+            vec![
+                ("latency count".to_string(), 4.0),
+                ("latency p50".to_string(), 20.0),
+                ("latency p90".to_string(), 30.0),
+                ("queue".to_string(), 7.0),
+                ("requests".to_string(), 5.0),
+                ("users".to_string(), 2.0),
+            ]
+        );
+
+        // Only gauges are kept between intervals.
+        assert_eq!(metrics(&mut aggregator), vec![("queue".to_string(), 7.0)]);
+    }
+
+    #[test]
+    fn forward() {
+        let riemann = TestServer::start();
+        let server = Server::bind("127.0.0.1:0", Duration::from_millis(100)).unwrap();
+        let addr = server.local_addr().unwrap();
+        let connect = riemann.connector();
+        thread::spawn(move || server.run(connect));
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.send_to(b"requests:1|c\nrequests:1|c", addr).unwrap();
+
+        let events = riemann.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].get_service(), "requests");
+        assert_eq!(events[0].get_metric_d(), 2.0);
+    }
+}
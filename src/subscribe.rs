@@ -0,0 +1,216 @@
+//! Live subscriptions to events matching a query.
+//!
+//! `Client::query` only returns a snapshot of the index. A `Subscription`
+//! connects to the websocket or server-sent events endpoint of a Riemann
+//! server and yields each matching event as it arrives, reconnecting if the
+//! connection is lost.
+
+use std::io::{BufRead, Error as IoError, ErrorKind};
+use std::net::TcpStream;
+use std::thread::sleep;
+use std::time::Duration;
+
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+use super::http;
+use super::proto::{Event, Query};
+use super::utils::Result;
+
+/// The default port of Riemann's websocket server.
+pub const WEBSOCKET_PORT: u16 = 5556;
+
+/// The default port of Riemann's server-sent events server.
+pub const SSE_PORT: u16 = 5558;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endpoint {
+    WebSocket,
+    ServerSentEvents,
+}
+
+enum Connection {
+    WebSocket(Box<WebSocket<MaybeTlsStream<TcpStream>>>),
+    ServerSentEvents(Box<dyn BufRead + Send>),
+}
+
+/// An iterator over events matching a query, as they are received by the server.
+///
+/// Connection and read errors are yielded as `Err` items. If a reconnect
+/// delay is set the next call to `next()` waits for the delay and reconnects,
+/// otherwise the iterator ends after the first connection error.
+pub struct Subscription {
+    endpoint: Endpoint,
+    host: String,
+    port: u16,
+    query: String,
+    reconnect: Option<Duration>,
+    connection: Option<Connection>,
+    failed: bool,
+}
+
+impl Subscription {
+    /// Subscribe using Riemann's websocket endpoint (`/index?subscribe=true`).
+    pub fn websocket<T: Into<Query>>(host: &str, port: u16, query: T) -> Self {
+        Subscription::new(Endpoint::WebSocket, host, port, query)
+    }
+
+    /// Subscribe using Riemann's server-sent events endpoint (`/index`).
+    pub fn sse<T: Into<Query>>(host: &str, port: u16, query: T) -> Self {
+        Subscription::new(Endpoint::ServerSentEvents, host, port, query)
+    }
+
+    pub fn new<T: Into<Query>>(endpoint: Endpoint, host: &str, port: u16, query: T) -> Self {
+        Subscription {
+            endpoint,
+            host: host.to_string(),
+            port,
+            query: query.into().get_string().to_string(),
+            reconnect: Some(Duration::from_secs(1)),
+            connection: None,
+            failed: false,
+        }
+    }
+
+    /// Set the delay before reconnecting, or `None` to stop after a connection error.
+    pub fn set_reconnect(&mut self, delay: Option<Duration>) {
+        self.reconnect = delay;
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        match self.endpoint {
+            Endpoint::WebSocket => {
+                let url = format!(
+                    "ws://{}:{}/index?subscribe=true&query={}",
+                    self.host,
+                    self.port,
+                    http::encode(&self.query)
+                );
+                let (socket, _) = tungstenite::connect(url)?;
+                Ok(Connection::WebSocket(Box::new(socket)))
+            }
+            Endpoint::ServerSentEvents => {
+                let path = format!("/index?query={}", http::encode(&self.query));
+                let response = http::get(&self.host, self.port, &path, "text/event-stream", None)?;
+                Ok(Connection::ServerSentEvents(response.body))
+            }
+        }
+    }
+}
+
+impl Connection {
+    /// Read the next message payload, or `None` if the message did not contain an event.
+    fn read(&mut self) -> Result<Option<String>> {
+        match *self {
+            Connection::WebSocket(ref mut socket) => match socket.read()? {
+                Message::Text(text) => Ok(Some(text)),
+                Message::Binary(data) => Ok(Some(String::from_utf8_lossy(&data).into_owned())),
+                Message::Close(_) => Err(closed()),
+                _ => Ok(None),
+            },
+            Connection::ServerSentEvents(ref mut reader) => read_sse(reader),
+        }
+    }
+}
+
+fn closed() -> super::Error {
+    IoError::new(ErrorKind::UnexpectedEof, "Subscription closed by server").into()
+}
+
+/// Read one server-sent event, returning the contents of its `data` fields.
+fn read_sse<R: BufRead + ?Sized>(reader: &mut R) -> Result<Option<String>> {
+    let mut data: Option<String> = None;
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(closed());
+        }
+
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Ok(data);
+        }
+
+        if let Some(value) = line.strip_prefix("data:") {
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match data {
+                Some(ref mut data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => data = Some(value.to_string()),
+            }
+        }
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.connection.is_none() {
+                if self.failed {
+                    match self.reconnect {
+                        Some(delay) => sleep(delay),
+                        None => return None,
+                    }
+                }
+
+                match self.connect() {
+                    Ok(connection) => {
+                        self.connection = Some(connection);
+                        self.failed = false;
+                    }
+                    Err(err) => {
+                        self.failed = true;
+                        return Some(Err(err));
+                    }
+                }
+            }
+
+            match self.connection.as_mut().unwrap().read() {
+                Ok(Some(payload)) => {
+                    return Some(serde_json::from_str(&payload).map_err(Into::into))
+                }
+                Ok(None) => continue,
+                Err(err) => {
+                    self.connection = None;
+                    self.failed = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+impl ::std::fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "Subscription {{ endpoint: {:?}, host: {:?}, port: {}, query: {:?} }}",
+            self.endpoint, self.host, self.port, self.query
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_sse;
+
+    #[test]
+    fn sse_events() {
+        let mut stream: &[u8] =
+            b": keepalive\n\ndata: {\"service\": \"a\"}\n\nevent: x\ndata: 1\r\ndata:2\r\n\r\n";
+
+        assert_eq!(read_sse(&mut stream).unwrap(), None);
+        assert_eq!(
+            read_sse(&mut stream).unwrap(),
+            Some("{\"service\": \"a\"}".to_string())
+        );
+        assert_eq!(read_sse(&mut stream).unwrap(), Some("1\n2".to_string()));
+        assert!(read_sse(&mut stream).is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! A `tracing` layer that sends span timings and errors to Riemann.
+//!
+//! When a span closes an event is sent with the span name as the service, the
+//! time since the span was created (in seconds) as `metric_d` and the span's
+//! recorded fields as attributes. `ERROR` level events are sent as events with
+//! a `critical` state and the event's message as the description.
+
+use std::fmt::Debug;
+use std::time::Instant;
+
+use ::tracing::field::{Field, Visit};
+use ::tracing::span::{Attributes, Id, Record};
+use ::tracing::{Level, Subscriber};
+use ::tracing_subscriber::layer::{Context, Layer};
+use ::tracing_subscriber::registry::LookupSpan;
+
+use super::client::BatchClient;
+use super::proto::{Attribute, Event};
+use super::utils::now_micros;
+
+/// A layer that sends span durations and errors through a `BatchClient`.
+#[derive(Debug)]
+pub struct RiemannLayer {
+    client: BatchClient,
+}
+
+/// The fields recorded on a span or event, in the order they were recorded.
+#[derive(Default)]
+struct Fields {
+    message: Option<String>,
+    values: Vec<(String, String)>,
+}
+
+impl Fields {
+    fn set(&mut self, field: &Field, value: String) {
+        if field.name() == "message" {
+            self.message = Some(value);
+        } else if let Some(entry) = self.values.iter_mut().find(|(k, _)| k == field.name()) {
+            entry.1 = value;
+        } else {
+            self.values.push((field.name().to_string(), value));
+        }
+    }
+
+    fn attributes(&self) -> Vec<Attribute> {
+        self.values
+            .iter()
+            .map(|(key, value)| {
+                let mut attribute = Attribute::new();
+                attribute.set_key(key.clone());
+                attribute.set_value(value.clone());
+                attribute
+            })
+            .collect()
+    }
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.set(field, value.to_string());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.set(field, format!("{:?}", value));
+    }
+}
+
+/// Data stored in the extensions of each span.
+struct Timing {
+    start: Instant,
+    fields: Fields,
+}
+
+fn new_event(service: &str, state: &str) -> Event {
+    let mut event = Event::new();
+    let micros = now_micros();
+    event.set_time(micros / 1_000_000);
+    event.set_time_micros(micros);
+    event.set_service(service.to_string());
+    event.set_state(state.to_string());
+    event.set_tags(vec!["tracing".to_string()].into());
+    event
+}
+
+impl RiemannLayer {
+    pub fn new(client: BatchClient) -> Self {
+        RiemannLayer { client }
+    }
+}
+
+impl<S> Layer<S> for RiemannLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut fields = Fields::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(Timing {
+                start: Instant::now(),
+                fields,
+            });
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<Timing>() {
+                values.record(&mut timing.fields);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &::tracing::Event<'_>, ctx: Context<'_, S>) {
+        if *event.metadata().level() != Level::ERROR {
+            return;
+        }
+
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+
+        let service = ctx.event_span(event).map_or_else(
+            || event.metadata().target().to_string(),
+            |span| span.name().to_string(),
+        );
+
+        let mut riemann_event = new_event(&service, "critical");
+        if let Some(message) = fields.message.take() {
+            riemann_event.set_description(message);
+        }
+        riemann_event.set_attributes(fields.attributes().into());
+        self.client.send(riemann_event);
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(&id) {
+            Some(span) => span,
+            None => return,
+        };
+        let extensions = span.extensions();
+        let timing = match extensions.get::<Timing>() {
+            Some(timing) => timing,
+            None => return,
+        };
+
+        let mut event = new_event(span.name(), "ok");
+        event.set_metric_d(timing.start.elapsed().as_secs_f64());
+        event.set_attributes(timing.fields.attributes().into());
+        self.client.send(event);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use ::tracing::{error, info, info_span};
+    use ::tracing_subscriber::layer::SubscriberExt;
+    use ::tracing_subscriber::Registry;
+
+    use super::super::client::test_server::TestServer;
+    use super::super::client::{BatchClient, BatchOptions};
+    use super::super::proto::Event;
+    use super::RiemannLayer;
+
+    fn attributes(event: &Event) -> Vec<(&str, &str)> {
+        event
+            .get_attributes()
+            .iter()
+            .map(|a| (a.get_key(), a.get_value()))
+            .collect()
+    }
+
+    #[test]
+    fn span_and_error_events() {
+        let server = TestServer::start();
+        let client = BatchClient::new(
+            server.connector(),
+            BatchOptions {
+                interval: Duration::from_secs(60),
+                ..BatchOptions::default()
+            },
+        );
+        let subscriber = Registry::default().with(RiemannLayer::new(client));
+
+        ::tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!(
+                "handle_request",
+                path = "/users",
+                status = ::tracing::field::Empty
+            );
+            let _guard = span.enter();
+            info!("ignored");
+            error!(code = 3, "query failed");
+            span.record("status", 500);
+        });
+
+        // The subscriber (and so the client) is dropped, which sends the batch.
+        let events = server.events();
+        assert_eq!(events.len(), 2);
+
+        assert_eq!(events[0].get_service(), "handle_request");
+        assert_eq!(events[0].get_state(), "critical");
+        assert_eq!(events[0].get_description(), "query failed");
+        assert_eq!(attributes(&events[0]), vec![("code", "3")]);
+
+        assert_eq!(events[1].get_service(), "handle_request");
+        assert_eq!(events[1].get_state(), "ok");
+        assert!(events[1].has_metric_d());
+        assert_eq!(
+            attributes(&events[1]),
+            vec![("path", "/users"), ("status", "500")]
+        );
+    }
+}
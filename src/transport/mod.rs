@@ -4,6 +4,7 @@ use std::net::{TcpStream, ToSocketAddrs};
 use std::time::Duration;
 
 use ::protobuf::{CodedInputStream, Message};
+use rustls::Session;
 
 use super::proto::{Event, Msg, Query};
 use super::utils::{Error, Result};
@@ -15,6 +16,10 @@ use std::io::BufReader;
 use std::io::Read;
 use std::io::Write;
 
+pub use self::stream::EventStream;
+
+mod stream;
+
 pub struct TCPTransport {
     stream: TcpStream,
     tls_sess: Option<rustls::ClientSession>,
@@ -73,8 +78,21 @@ impl TCPTransport {
         )?;
 
         let dns_name = webpki::DNSNameRef::try_from_ascii_str(hostname)?;
-        let sess = rustls::ClientSession::new(&Arc::new(config), dns_name);
-        let stream = TcpStream::connect(addr)?;
+        let mut sess = rustls::ClientSession::new(&Arc::new(config), dns_name);
+        let mut stream = TcpStream::connect(addr)?;
+
+        // Finish the handshake now, so certificate errors are reported when connecting.
+        while sess.is_handshaking() {
+            sess.complete_io(&mut stream)
+                .map_err(|e| match tls_error(Error::Io(e)) {
+                    Error::Io(e) => Error::TLS(rustls::TLSError::General(format!(
+                        "Handshake failed: {}",
+                        e
+                    ))),
+                    e => e,
+                })?;
+        }
+
         Ok(TCPTransport {
             stream,
             tls_sess: Some(sess),
@@ -92,7 +110,8 @@ impl TCPTransport {
             Some(_) => send_msg(
                 rustls::Stream::new(self.tls_sess.as_mut().unwrap(), &mut self.stream),
                 msg,
-            ),
+            )
+            .map_err(tls_error),
             None => send_msg(&mut self.stream, msg),
         }
     }
@@ -112,14 +131,49 @@ impl TCPTransport {
             msg
         })
     }
+
+    /// Send a query, returning an iterator that decodes events from the response as it is read.
+    pub fn send_query_stream(&mut self, query: Query) -> Result<EventStream<'_>> {
+        let mut msg = Msg::new();
+        msg.set_query(query);
+
+        let mut stream: Box<dyn ReadWrite> = match self.tls_sess.as_mut() {
+            Some(sess) => Box::new(rustls::Stream::new(sess, &mut self.stream)),
+            None => Box::new(&mut self.stream),
+        };
+
+        write_msg(&mut stream, &msg).map_err(tls_error)?;
+        let size = read_size(&mut stream).map_err(tls_error)?;
+        Ok(EventStream::new(stream, size))
+    }
 }
 
+/// A stream that can be both read from and written to, plain or encrypted.
+trait ReadWrite: Read + Write {}
+
+impl<T: Read + Write> ReadWrite for T {}
+
 impl ::std::fmt::Debug for TCPTransport {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
         write!(f, "TCPTransport {{ addr: {:?} }}", self.stream.peer_addr())
     }
 }
 
+/// Turn an IO error caused by the TLS session, such as the server rejecting
+/// the client certificate, into a TLS error.
+fn tls_error(error: Error) -> Error {
+    let io_error = match error {
+        Error::Io(ref e) => Some(e),
+        Error::Protobuf(::protobuf::ProtobufError::IoError(ref e)) => Some(e),
+        _ => None,
+    };
+    let tls = io_error
+        .and_then(|e| e.get_ref())
+        .and_then(|e| e.downcast_ref::<rustls::TLSError>())
+        .cloned();
+    tls.map_or(error, Error::TLS)
+}
+
 fn load_certs(filename: &str) -> Result<Vec<rustls::Certificate>> {
     let certfile = File::open(filename)?;
     let mut reader = BufReader::new(certfile);
@@ -146,7 +200,7 @@ fn load_private_key(filename: &str) -> Result<rustls::PrivateKey> {
     Err(Error::Key("Key not found".to_string()))
 }
 
-fn send_msg<T: Read + Write>(mut stream: T, msg: Msg) -> Result<Msg> {
+fn write_msg<T: Write>(stream: &mut T, msg: &Msg) -> Result<()> {
     // Prepare the message for writing.
     let size = msg.compute_size();
     let bytes = msg.write_to_bytes()?;
@@ -168,6 +222,18 @@ fn send_msg<T: Read + Write>(mut stream: T, msg: Msg) -> Result<Msg> {
     // Write the rest of the message.
     stream.write_all(&bytes)?;
     stream.flush()?;
+    Ok(())
+}
+
+/// Read a message size as a big-endian 32 bit unsigned integer.
+fn read_size<T: Read>(stream: &mut T) -> Result<u32> {
+    let mut size = [0u8; 4];
+    stream.read_exact(&mut size)?;
+    Ok(u32::from_be_bytes(size))
+}
+
+fn send_msg<T: Read + Write>(mut stream: T, msg: Msg) -> Result<Msg> {
+    write_msg(&mut stream, &msg)?;
 
     // CodedInputStream is used for the `read_raw_byte(s)` methods
     let mut input_stream = CodedInputStream::new(&mut stream);
@@ -293,4 +359,61 @@ mod tests {
             Err(rustls::TLSError::General("invalid private key".to_string()))
         );
     }
+
+    /// Accept one connection with a server that requires a client
+    /// certificate signed by the test CA.
+    fn tls_server() -> u16 {
+        use std::net::TcpListener;
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(&load_certs("test_certs/tls_ca.pem").unwrap()[0])
+            .unwrap();
+        let mut config = rustls::ServerConfig::new(rustls::AllowAnyAuthenticatedClient::new(roots));
+        config
+            .set_single_cert(
+                load_certs("test_certs/tls_server.pem").unwrap(),
+                load_private_key("test_certs/tls_server_key").unwrap(),
+            )
+            .unwrap();
+        let config = Arc::new(config);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut session = rustls::ServerSession::new(&config);
+            while session.complete_io(&mut stream).is_ok() {}
+            // Wait for the client to close, so it can read the alert rather
+            // than having its connection reset.
+            let _ = std::io::copy(&mut stream, &mut std::io::sink());
+        });
+        port
+    }
+
+    fn send_tls(ca_file: &str, cert_file: &str, key_file: &str) -> Result<Msg> {
+        let port = tls_server();
+        TCPTransport::connect_tls("localhost", port, ca_file, cert_file, key_file)
+            .and_then(|mut transport| transport.send_events(Vec::new()))
+    }
+
+    #[test]
+    fn test_rejected_client_cert() {
+        let result = send_tls(
+            "test_certs/tls_ca.pem",
+            "test_certs/tls_other_client.pem",
+            "test_certs/tls_other_client_key",
+        );
+        assert!(matches!(result, Err(Error::TLS(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_untrusted_server_cert() {
+        let result = send_tls(
+            "test_certs/tls_other_client.pem",
+            "test_certs/tls_client.pem",
+            "test_certs/tls_client_key",
+        );
+        assert!(matches!(result, Err(Error::TLS(_))), "{:?}", result);
+    }
 }
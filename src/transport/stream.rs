@@ -0,0 +1,263 @@
+//! Incremental decoding of the events in a query response.
+
+use std::io::{self, BufReader, Read, Take};
+
+use ::protobuf::error::{ProtobufError, WireError};
+use ::protobuf::wire_format::{Tag, WireType};
+use ::protobuf::Message;
+
+use super::super::proto::Event;
+use super::super::utils::{Error, Result};
+
+/// Field numbers from the `Msg` message in `mod.proto`.
+const MSG_OK: u32 = 2;
+const MSG_ERROR: u32 = 3;
+const MSG_EVENTS: u32 = 6;
+
+/// An iterator over the events in a response, decoded one at a time as they
+/// are read from the stream.
+///
+/// If the server responds with `ok: false` an `Err` is yielded and the
+/// iteration ends. Dropping the iterator before the end of the response reads
+/// and discards the rest of it, so the connection can be used again.
+pub struct EventStream<'a> {
+    reader: BufReader<Take<Box<dyn Read + 'a>>>,
+    ok: Option<bool>,
+    error: String,
+    done: bool,
+}
+
+fn wire_error(error: WireError) -> Error {
+    Error::Protobuf(ProtobufError::WireError(error))
+}
+
+impl<'a> EventStream<'a> {
+    /// Decode a response of `size` bytes from `reader`.
+    pub fn new<R: Read + 'a>(reader: R, size: u32) -> Self {
+        let reader: Box<dyn Read + 'a> = Box::new(reader);
+        EventStream {
+            reader: BufReader::new(reader.take(u64::from(size))),
+            ok: None,
+            error: String::new(),
+            done: false,
+        }
+    }
+
+    /// Read a varint, or return `None` at the end of the response.
+    fn read_varint(&mut self) -> Result<Option<u64>> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0u8];
+            if self.reader.read(&mut byte)? == 0 {
+                return if shift == 0 {
+                    Ok(None)
+                } else {
+                    Err(wire_error(WireError::TruncatedMessage))
+                };
+            }
+            value |= u64::from(byte[0] & 0x7F) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(value));
+            }
+        }
+        Err(wire_error(WireError::IncorrectVarint))
+    }
+
+    fn read_bytes(&mut self, length: u64) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        (&mut self.reader).take(length).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != length {
+            return Err(wire_error(WireError::TruncatedMessage));
+        }
+        Ok(bytes)
+    }
+
+    fn read_length_delimited(&mut self) -> Result<Vec<u8>> {
+        let length = self
+            .read_varint()?
+            .ok_or_else(|| wire_error(WireError::TruncatedMessage))?;
+        self.read_bytes(length)
+    }
+
+    /// Read fields from the response until the next event or the end of the response.
+    fn read_event(&mut self) -> Result<Option<Event>> {
+        loop {
+            let tag = match self.read_varint()? {
+                Some(tag) => tag as u32,
+                None => {
+                    return if self.ok == Some(true) {
+                        Ok(None)
+                    } else {
+                        Err(Error::Riemann(self.error.clone()))
+                    }
+                }
+            };
+
+            let (field, wire_type) = Tag::new(tag)
+                .ok_or_else(|| wire_error(WireError::IncorrectTag(tag)))?
+                .unpack();
+
+            match (field, wire_type) {
+                (MSG_OK, WireType::WireTypeVarint) => {
+                    let ok = self.read_varint()? != Some(0);
+                    self.ok = Some(ok);
+                    if !ok && !self.error.is_empty() {
+                        return Err(Error::Riemann(self.error.clone()));
+                    }
+                }
+                (MSG_ERROR, WireType::WireTypeLengthDelimited) => {
+                    let bytes = self.read_length_delimited()?;
+                    self.error =
+                        String::from_utf8(bytes).map_err(|_| wire_error(WireError::Utf8Error))?;
+                    if self.ok == Some(false) {
+                        return Err(Error::Riemann(self.error.clone()));
+                    }
+                }
+                (MSG_EVENTS, WireType::WireTypeLengthDelimited) => {
+                    let bytes = self.read_length_delimited()?;
+                    return Ok(Some(Event::parse_from_bytes(&bytes)?));
+                }
+                (_, WireType::WireTypeVarint) => {
+                    self.read_varint()?;
+                }
+                (_, WireType::WireTypeFixed64) => {
+                    self.read_bytes(8)?;
+                }
+                (_, WireType::WireTypeFixed32) => {
+                    self.read_bytes(4)?;
+                }
+                (_, WireType::WireTypeLengthDelimited) => {
+                    self.read_length_delimited()?;
+                }
+                (_, wire_type) => return Err(wire_error(WireError::UnexpectedWireType(wire_type))),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for EventStream<'a> {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_event() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl<'a> Drop for EventStream<'a> {
+    fn drop(&mut self) {
+        // Errors are ignored, as the connection is unusable if the response can't be read.
+        let _ = io::copy(&mut self.reader, &mut io::sink());
+    }
+}
+
+impl<'a> ::std::fmt::Debug for EventStream<'a> {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(
+            f,
+            "EventStream {{ remaining: {}, done: {} }}",
+            self.reader.get_ref().limit() as usize + self.reader.buffer().len(),
+            self.done
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+
+    use ::protobuf::Message;
+
+    use super::super::super::proto::{Event, Msg};
+    use super::super::super::utils::Error;
+    use super::EventStream;
+
+    fn response(ok: bool, error: &str, services: &[&str]) -> Vec<u8> {
+        let mut msg = Msg::new();
+        msg.set_ok(ok);
+        if !error.is_empty() {
+            msg.set_error(error.to_string());
+        }
+        msg.set_events(
+            services
+                .iter()
+                .map(|service| {
+                    let mut event = Event::new();
+                    event.set_service(service.to_string());
+                    event
+                })
+                .collect(),
+        );
+        msg.write_to_bytes().unwrap()
+    }
+
+    fn services(stream: EventStream) -> Vec<String> {
+        stream
+            .map(|event| event.unwrap().get_service().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn decode_events() {
+        let bytes = response(true, "", &["a", "b", "c"]);
+        let stream = EventStream::new(Cursor::new(&bytes), bytes.len() as u32);
+        assert_eq!(services(stream), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn decode_empty() {
+        let bytes = response(true, "", &[]);
+        let stream = EventStream::new(Cursor::new(&bytes), bytes.len() as u32);
+        assert!(services(stream).is_empty());
+    }
+
+    #[test]
+    fn decode_error() {
+        let bytes = response(false, "parse error", &[]);
+        let mut stream = EventStream::new(Cursor::new(&bytes), bytes.len() as u32);
+        match stream.next() {
+            Some(Err(Error::Riemann(ref error))) if error == "parse error" => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(stream.next().is_none());
+    }
+
+    #[test]
+    fn decode_truncated() {
+        let bytes = response(true, "", &["a", "b"]);
+        let size = bytes.len() as u32 - 1;
+        let mut stream = EventStream::new(Cursor::new(&bytes), size);
+        assert!(stream.next().unwrap().is_ok());
+        assert!(stream.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn drop_drains_response() {
+        let mut bytes = response(true, "", &["a", "b", "c"]);
+        let size = bytes.len() as u32;
+        bytes.extend_from_slice(b"next");
+
+        let mut cursor = Cursor::new(&bytes);
+        {
+            let mut stream = EventStream::new(&mut cursor, size);
+            assert!(stream.next().is_some());
+        }
+
+        let mut rest = String::new();
+        cursor.read_to_string(&mut rest).unwrap();
+        assert_eq!(rest, "next");
+    }
+}
@@ -0,0 +1,246 @@
+//! Track query results over time and draw them as a table in a terminal.
+//!
+//! A `Watch` keeps the latest event for each host and service. Each update
+//! records whether an event's state changed and how much its metric changed
+//! since the last update, so they can be highlighted when the table is drawn.
+
+use std::io::{self, Write};
+
+use super::client::Order;
+use super::proto::Event;
+
+/// An event and how it changed since the last update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Row {
+    pub event: Event,
+    /// The previous state, if the state changed.
+    pub previous_state: Option<String>,
+    /// The change in metric, if the event and the previous event have metrics.
+    pub delta: Option<f64>,
+}
+
+impl Row {
+    fn new(event: Event, previous: Option<&Event>) -> Self {
+        let previous_state = previous
+            .filter(|p| p.get_state() != event.get_state())
+            .map(|p| p.get_state().to_string());
+        let delta = match (previous.and_then(Event::metric), event.metric()) {
+            (Some(previous), Some(current)) => Some(current.as_f64() - previous.as_f64()),
+            _ => None,
+        };
+        Row {
+            event,
+            previous_state,
+            delta,
+        }
+    }
+
+    fn is(&self, event: &Event) -> bool {
+        self.event.get_host() == event.get_host() && self.event.get_service() == event.get_service()
+    }
+}
+
+/// The latest events matching a query, sorted by host and service.
+#[derive(Debug)]
+pub struct Watch {
+    rows: Vec<Row>,
+    order: Order,
+}
+
+impl Default for Watch {
+    fn default() -> Self {
+        Watch::new()
+    }
+}
+
+const HEADER: [&str; 5] = ["HOST", "SERVICE", "STATE", "METRIC", "DELTA"];
+
+fn color(state: &str) -> &'static str {
+    match state {
+        "ok" => "32",
+        "warning" => "33",
+        "critical" | "failure" => "31",
+        _ => "0",
+    }
+}
+
+impl Watch {
+    pub fn new() -> Self {
+        Watch {
+            rows: Vec::new(),
+            order: Order::HostService,
+        }
+    }
+
+    /// Set how rows are sorted.
+    pub fn set_order(&mut self, order: Order) {
+        self.order = order;
+        self.sort();
+    }
+
+    fn sort(&mut self) {
+        let order = &self.order;
+        self.rows.sort_by(|a, b| order.compare(&a.event, &b.event));
+    }
+
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+
+    /// Replace the rows with new results of a query, comparing each event to
+    /// the previous results.
+    pub fn update(&mut self, events: Vec<Event>) {
+        let rows = events
+            .into_iter()
+            .map(|event| {
+                let previous = self.rows.iter().find(|row| row.is(&event));
+                Row::new(event, previous.map(|row| &row.event))
+            })
+            .collect();
+        self.rows = rows;
+        self.sort();
+    }
+
+    /// Add an event, or replace the event with the same host and service, as
+    /// when receiving events from a subscription.
+    pub fn insert(&mut self, event: Event) {
+        match self.rows.iter_mut().find(|row| row.is(&event)) {
+            Some(row) => *row = Row::new(event, Some(&row.event)),
+            None => self.rows.push(Row::new(event, None)),
+        }
+        self.sort();
+    }
+
+    /// Draw the rows as a table under a title line.
+    ///
+    /// If `terminal` is true the screen is cleared first, states are
+    /// coloured and changed states are shown in bold.
+    pub fn render<W: Write>(&self, out: &mut W, title: &str, terminal: bool) -> io::Result<()> {
+        let cells: Vec<[String; 5]> = self
+            .rows
+            .iter()
+            .map(|row| {
+                let state = match row.previous_state {
+                    Some(ref previous) => format!("{} -> {}", previous, row.event.get_state()),
+                    None => row.event.get_state().to_string(),
+                };
+                let delta = match row.delta {
+                    Some(delta) if delta > 0.0 => format!("+{}", delta),
+                    Some(delta) => delta.to_string(),
+                    None => String::new(),
+                };
+                [
+                    row.event.get_host().to_string(),
+                    row.event.get_service().to_string(),
+                    state,
+                    row.event.metric().map_or(String::new(), |m| m.to_string()),
+                    delta,
+                ]
+            })
+            .collect();
+
+        let mut widths = HEADER.map(str::len);
+        for row in cells.iter() {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.chars().count());
+            }
+        }
+
+        if terminal {
+            write!(out, "\x1b[H\x1b[2J")?;
+        }
+        writeln!(out, "{} ({} events)", title, self.rows.len())?;
+        writeln!(out)?;
+
+        let header: Vec<String> = HEADER
+            .iter()
+            .zip(widths)
+            .map(|(name, width)| format!("{:<width$}", name, width = width))
+            .collect();
+        writeln!(out, "{}", header.join(" ").trim_end())?;
+
+        for (row, cells) in self.rows.iter().zip(cells) {
+            let mut line: Vec<String> = cells
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            if terminal {
+                let style = match row.previous_state {
+                    Some(_) => format!("1;{}", color(row.event.get_state())),
+                    None => color(row.event.get_state()).to_string(),
+                };
+                line[2] = format!("\x1b[{}m{}\x1b[0m", style, line[2]);
+            }
+            writeln!(out, "{}", line.join(" ").trim_end())?;
+        }
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::proto::Event;
+    use super::Watch;
+
+    fn event(host: &str, service: &str, state: &str, metric: i64) -> Event {
+        let mut event = Event::new();
+        event.set_host(host.to_string());
+        event.set_service(service.to_string());
+        event.set_state(state.to_string());
+        event.set_metric_sint64(metric);
+        event
+    }
+
+    fn render(watch: &Watch) -> String {
+        let mut out = Vec::new();
+        watch.render(&mut out, "Every 2s: true", false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn update() {
+        let mut watch = Watch::new();
+        watch.update(vec![
+            event("web02", "cpu", "ok", 10),
+            event("web01", "cpu", "ok", 20),
+        ]);
+        assert_eq!(watch.rows()[0].event.get_host(), "web01");
+        assert_eq!(watch.rows()[0].delta, None);
+
+        watch.update(vec![
+            event("web01", "cpu", "critical", 95),
+            event("web02", "cpu", "ok", 8),
+            event("web03", "cpu", "ok", 1),
+        ]);
+        assert_eq!(
+            render(&watch),
+            "Every 2s: true (3 events)\n\
+             \n\
+             HOST  SERVICE STATE          METRIC DELTA\n\
+             web01 cpu     ok -> critical 95     +75\n\
+             web02 cpu     ok             8      -2\n\
+             web03 cpu     ok             1\n"
+        );
+    }
+
+    #[test]
+    fn insert() {
+        let mut watch = Watch::new();
+        watch.update(vec![event("web02", "cpu", "ok", 10)]);
+        watch.insert(event("web01", "cpu", "ok", 5));
+        watch.insert(event("web02", "cpu", "warning", 12));
+
+        let rows = watch.rows();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].event.get_host(), "web01");
+        assert_eq!(rows[1].previous_state.as_deref(), Some("ok"));
+        assert_eq!(rows[1].delta, Some(2.0));
+
+        let mut out = Vec::new();
+        watch.render(&mut out, "true", true).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("\x1b[H\x1b[2J"));
+        assert!(out.contains("\x1b[1;33mok -> warning\x1b[0m"));
+    }
+}
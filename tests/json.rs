@@ -0,0 +1,122 @@
+#![cfg(feature = "serde")]
+
+use riemann_client::proto::{Attribute, Event};
+
+fn round_trip(event: &Event) -> Event {
+    serde_json::from_str(&serde_json::to_string(event).unwrap()).unwrap()
+}
+
+fn attribute(key: &str, value: &str) -> Attribute {
+    let mut attribute = Attribute::new();
+    attribute.set_key(key.to_string());
+    attribute.set_value(value.to_string());
+    attribute
+}
+
+#[test]
+fn serialize_event() {
+    let mut event = Event::new();
+    event.set_host("www1".to_string());
+    event.set_service("api".to_string());
+    event.set_metric_sint64(3);
+    event.set_tags(vec!["a".to_string()].into());
+    event.set_time(1_432_130_773);
+    event.set_attributes(vec![attribute("env", "prod")].into());
+
+    assert_eq!(
+        serde_json::to_value(&event).unwrap(),
+        serde_json::json!({
+            "host": "www1",
+            "service": "api",
+            "metric": 3,
+            "tags": ["a"],
+            "time": "2015-05-20T14:06:13.000Z",
+            "env": "prod",
+        })
+    );
+}
+
+#[test]
+fn deserialize_event() {
+    let event: Event = serde_json::from_str(
+        r#"{"host": "www1", "service": "api", "state": "ok", "description": null,
+            "metric": 2.5, "tags": ["a", "b"], "time": "2015-05-20T14:06:13.123Z",
+            "ttl": 60, "env": "prod", "shard": 3}"#,
+    )
+    .unwrap();
+
+    assert_eq!(event.get_host(), "www1");
+    assert_eq!(event.get_service(), "api");
+    assert_eq!(event.get_state(), "ok");
+    assert!(!event.has_description());
+    assert_eq!(event.get_metric_d(), 2.5);
+    assert_eq!(event.get_metric_f(), 2.5);
+    assert!(!event.has_metric_sint64());
+    assert_eq!(event.get_tags(), &["a".to_string(), "b".to_string()]);
+    assert_eq!(event.get_time(), 1_432_130_773);
+    assert_eq!(event.get_time_micros(), 1_432_130_773_123_000);
+    assert_eq!(event.get_ttl(), 60.0);
+    assert_eq!(
+        event.get_attributes(),
+        &[attribute("env", "prod"), attribute("shard", "3")]
+    );
+}
+
+#[test]
+fn deserialize_invalid() {
+    assert!(serde_json::from_str::<Event>("[]").is_err());
+    assert!(serde_json::from_str::<Event>(r#"{"metric": "high"}"#).is_err());
+    assert!(serde_json::from_str::<Event>(r#"{"time": "yesterday"}"#).is_err());
+}
+
+#[test]
+fn round_trip_fields() {
+    let mut event = Event::new();
+    event.set_host("www1".to_string());
+    event.set_service("api".to_string());
+    event.set_state("critical".to_string());
+    event.set_description("it \"broke\"".to_string());
+    event.set_tags(vec!["a".to_string(), "b".to_string()].into());
+    event.set_ttl(30.5);
+    event.set_time(1_432_130_773);
+    event.set_attributes(vec![attribute("env", "prod"), attribute("shard", "3")].into());
+
+    assert_eq!(round_trip(&event), event);
+}
+
+#[test]
+fn round_trip_time_micros() {
+    let mut event = Event::new();
+    event.set_time_micros(1_432_130_773_123_456);
+
+    let decoded = round_trip(&event);
+    assert_eq!(decoded.get_time(), 1_432_130_773);
+    assert_eq!(decoded.get_time_micros(), 1_432_130_773_123_456);
+}
+
+#[test]
+fn round_trip_metric_sint64() {
+    let mut event = Event::new();
+    event.set_metric_sint64(-42);
+    assert_eq!(round_trip(&event), event);
+}
+
+#[test]
+fn round_trip_metric_d() {
+    let mut event = Event::new();
+    event.set_metric_d(3.0);
+
+    let decoded = round_trip(&event);
+    assert_eq!(decoded.get_metric_d(), 3.0);
+    assert!(!decoded.has_metric_sint64());
+}
+
+#[test]
+fn round_trip_metric_f() {
+    let mut event = Event::new();
+    event.set_metric_f(0.1);
+
+    let decoded = round_trip(&event);
+    assert_eq!(decoded.get_metric_f(), 0.1);
+    assert!(!decoded.has_metric_sint64());
+}